@@ -0,0 +1,100 @@
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use deer_defense::common::EntityKind;
+use deer_defense::common::SpriteName;
+use deer_defense::common::WorldInfo;
+use deer_defense::entities::EntityManager;
+use deer_defense::entities::WORLD_SIZE;
+use deer_defense::renderer::RecordingRenderer;
+use engine_2d::math::Mat3;
+use engine_2d::math::Vec2;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+/// same seed every run so the numbers compare across commits
+const SEED: u64 = 0xdee7;
+const HUNTER_COUNTS: [usize; 3] = [100, 1_000, 5_000];
+const DT: f32 = 1.0 / 60.0;
+
+/// the tile forest, the trees and `hunters` hunters walking to the base
+fn make_world<'e>(hunters: usize) -> EntityManager<'e, 'e> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut ents = EntityManager::default();
+    ents.set_max_entities_warn(None);
+    let world = WorldInfo {
+        seed: SEED,
+        size: WORLD_SIZE,
+        local_statics: true,
+    };
+    ents.create_forest(world);
+    ents.plant_trees(world, SpriteName::Forest);
+
+    let bound = WORLD_SIZE as f32;
+    for _ in 0..hunters {
+        let x = rng.gen_range(-bound..bound);
+        let y = rng.gen_range(-bound..bound);
+        let pos = Vec2::new(x, y);
+        let dir = Vec2::default() - pos;
+        let kind = EntityKind::Enemy;
+        ents.spawn(pos, 5.25, 24.0, dir.angle(), dir, SpriteName::Hunter, kind);
+    }
+    ents
+}
+
+fn tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    for hunters in HUNTER_COUNTS {
+        let mut ents = make_world(hunters);
+        group.bench_with_input(BenchmarkId::from_parameter(hunters), &hunters, |b, _| {
+            b.iter(|| ents.tick(black_box(DT)))
+        });
+    }
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let hunters = HUNTER_COUNTS[1];
+    let mut ents = make_world(hunters);
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let count = ents.count() as i32;
+    let ids = (0..1024)
+        .map(|_| rng.gen_range(0..count))
+        .collect::<Vec<_>>();
+
+    c.bench_function("get", |b| {
+        b.iter(|| {
+            for &id in ids.iter() {
+                black_box(ents.get(black_box(id)).pos());
+            }
+        })
+    });
+    c.bench_function("get_mut", |b| {
+        b.iter(|| {
+            for &id in ids.iter() {
+                ents.get_mut(black_box(id)).set_rotation(0.0);
+            }
+        })
+    });
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    for hunters in HUNTER_COUNTS {
+        let ents = make_world(hunters);
+        group.bench_with_input(BenchmarkId::from_parameter(hunters), &hunters, |b, _| {
+            b.iter(|| {
+                let mut renderer = RecordingRenderer::default();
+                ents.render_with(&mut renderer, Mat3::scale(Vec2::new(1.0, 1.0)));
+                black_box(renderer.calls.len())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tick, lookup, render);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+use engine_2d::math::Mat3;
+use engine_2d::math::Vec2;
+
+use crate::entities::WORLD_SIZE;
+use crate::renderer::Transform;
+
+pub struct Camera {
+    pub center: Vec2,
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    /// the whole world, centered at 0,0
+    fn default() -> Self {
+        Self {
+            center: Vec2::default(),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn follow(&mut self, target: Vec2) {
+        self.center = target;
+    }
+
+    /// maps world space to NDC
+    pub fn view(&self) -> Mat3 {
+        self.transform().matrix()
+    }
+
+    /// where `pos` lands on screen, what `view` does to it, anything outside [-1, 1] is off
+    /// screen
+    pub fn to_ndc(&self, pos: Vec2) -> Vec2 {
+        self.transform().apply(pos)
+    }
+
+    /// the world to NDC mapping both `view` and `to_ndc` are built from
+    fn transform(&self) -> Transform {
+        let scale = self.scale();
+        Transform {
+            // scaling about the center first is the same as moving the scaled center to 0,0
+            pos: Vec2::default() - scale * self.center,
+            rotation: 0.0,
+            scale: Vec2::new(scale, scale),
+        }
+    }
+
+    fn scale(&self) -> f32 {
+        // at zoom 1 the world is 128 * 128 tiles
+        self.zoom / (WORLD_SIZE as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ndc(camera: &Camera, pos: Vec2, x: f32, y: f32) {
+        let ndc = camera.to_ndc(pos);
+        assert!(
+            (ndc.x - x).abs() < 1e-5 && (ndc.y - y).abs() < 1e-5,
+            "{:?} maps to {:?}, not ({}, {})",
+            pos,
+            ndc,
+            x,
+            y
+        );
+    }
+
+    #[test]
+    fn the_default_camera_shows_the_whole_world() {
+        let camera = Camera::default();
+        let edge = WORLD_SIZE as f32;
+        assert_ndc(&camera, Vec2::default(), 0.0, 0.0);
+        assert_ndc(&camera, Vec2::new(edge, edge), 1.0, 1.0);
+        assert_ndc(&camera, Vec2::new(-edge, edge), -1.0, 1.0);
+        assert_ndc(&camera, Vec2::new(edge / 2.0, -edge), 0.5, -1.0);
+    }
+
+    #[test]
+    fn following_centers_the_target_and_zoom_narrows_the_view() {
+        let mut camera = Camera::default();
+        let target = Vec2::new(10.0, -20.0);
+        camera.follow(target);
+        assert_ndc(&camera, target, 0.0, 0.0);
+
+        camera.zoom = 4.0;
+        let edge = WORLD_SIZE as f32 / 4.0;
+        assert_ndc(&camera, target + Vec2::new(edge, -edge), 1.0, -1.0);
+        assert_ndc(
+            &camera,
+            Vec2::default(),
+            -10.0 * 4.0 / 64.0,
+            20.0 * 4.0 / 64.0,
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use engine_2d::math::Vec2;
 
+use crate::math_ext::Vec2Ext;
 use crate::socket;
 use crate::socket::Error;
 use crate::socket::Packet;
@@ -9,7 +10,33 @@ use crate::socket::Result;
 
 pub const TIMEOUT: Duration = Duration::from_secs(3);
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+/// how many times faster the shot cooldown runs with rapid fire
+pub const RAPID_FIRE_RATE: u32 = 2;
+/// degrees between the spits of a spread shot
+pub const SPREAD_ANGLE: f32 = 15.0;
+
+/// one line of the desync log, formatted the same on both sides so the dumps diff cleanly
+/// `server_time` lines the two sides up in time, clients that haven't synced yet log `-`
+pub fn log_position(seq: u32, server_time: Option<Duration>, id: i32, kind: EntityKind, pos: Vec2) {
+    let time = server_time.map_or("-".to_owned(), |t| t.as_millis().to_string());
+    println!(
+        "pos {} {} {} {:?} {:.3} {:.3}",
+        seq, time, id, kind, pos.x, pos.y
+    );
+}
+
+/// one direction per spit in a shot, in the order their correlation ids are assigned
+pub fn shot_directions(aim: Vec2, spread: bool) -> Vec<Vec2> {
+    let angles: &[f32] = if spread {
+        &[-SPREAD_ANGLE, 0.0, SPREAD_ANGLE]
+    } else {
+        &[0.0]
+    };
+
+    angles.iter().map(|&angle| aim.rotate(angle)).collect()
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum SpriteName {
     None,
     Tile,
@@ -20,11 +47,72 @@ pub enum SpriteName {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     EntitySpawn = socket::OpCode::UserDefined as _,
     EntityUpdate,
     EntityDestroy,
+    SpawnAck,
+    /// debug request for every entity the server knows about
+    ListEntities,
+    /// reply to ListEntities, may span several packets
+    EntityList,
+    /// two entities hit each other, sent before they're destroyed
+    Collision,
+    /// the round is lost, the world resets shortly after
+    GameOver,
+    /// a new round starts, sent once the previous round's entities are destroyed
+    WorldReset,
+    /// sent by clients that want the round to begin, repeated until WaveStart
+    Ready,
+    /// every client is ready, hunters are on their way
+    WaveStart,
+    /// sent to a client whose deer picked up a powerup
+    PowerupApplied,
+    /// a client asks the server to spit for it
+    Fire,
+    /// tells the shooter which server entity its predicted spit became
+    FireAck,
+    /// a client's held keys for one frame
+    Input,
+    /// a hunter was stunned, or its stun wore off
+    Stun,
+    /// sent by a client before it spawns its deer, so it gets the deer back after a reconnect
+    Identify,
+}
+
+impl OpCode {
+    pub const ALL: [OpCode; 17] = [
+        OpCode::EntitySpawn,
+        OpCode::EntityUpdate,
+        OpCode::EntityDestroy,
+        OpCode::SpawnAck,
+        OpCode::ListEntities,
+        OpCode::EntityList,
+        OpCode::Collision,
+        OpCode::GameOver,
+        OpCode::WorldReset,
+        OpCode::Ready,
+        OpCode::WaveStart,
+        OpCode::PowerupApplied,
+        OpCode::Fire,
+        OpCode::FireAck,
+        OpCode::Input,
+        OpCode::Stun,
+        OpCode::Identify,
+    ];
+
+    /// opcodes off the wire are checked with this before they're converted
+    pub fn is_known(opcode: u8) -> bool {
+        Self::ALL.into_iter().any(|op| u8::from(op) == opcode)
+    }
+
+    /// makes packet logs show game opcodes by name
+    pub fn register_names() {
+        for op in Self::ALL {
+            socket::register_opcode_name(op.into(), &format!("{op:?}"));
+        }
+    }
 }
 
 impl From<u8> for OpCode {
@@ -40,17 +128,111 @@ impl From<OpCode> for u8 {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EntityKind {
     Tile,
     Forest,
     Player,
     PlayerProjectile,
     Enemy,
+    /// cosmetic and client side only, never networked and never collides
+    Effect,
+    /// dropped by hunters, grants a powerup to the deer that walks over it
+    Pickup,
+    /// built from a map file, hunters walk around it
+    Wall,
+}
+
+impl EntityKind {
+    pub const ALL: [EntityKind; 8] = [
+        EntityKind::Tile,
+        EntityKind::Forest,
+        EntityKind::Player,
+        EntityKind::PlayerProjectile,
+        EntityKind::Enemy,
+        EntityKind::Effect,
+        EntityKind::Pickup,
+        EntityKind::Wall,
+    ];
+
+    /// moved only by the server, clients follow its updates instead of simulating
+    pub fn server_owned(self) -> bool {
+        matches!(self, EntityKind::Enemy)
+    }
+
+    /// never moves or expires, so it's never ticked
+    pub fn is_static(self) -> bool {
+        matches!(
+            self,
+            EntityKind::Tile | EntityKind::Forest | EntityKind::Wall
+        )
+    }
+
+    /// mobs mirror their sprite to face the way they're moving
+    pub fn flips(self) -> bool {
+        matches!(self, EntityKind::Player | EntityKind::Enemy)
+    }
+
+    /// what clients draw it with
+    pub fn sprite(self) -> SpriteName {
+        match self {
+            EntityKind::Tile => SpriteName::Tile,
+            EntityKind::Forest => SpriteName::Forest,
+            EntityKind::Player => SpriteName::Deer,
+            EntityKind::PlayerProjectile => SpriteName::Spit,
+            EntityKind::Enemy => SpriteName::Hunter,
+            EntityKind::Pickup => SpriteName::Spit,
+            // no wall art yet, a wall of pines reads well enough
+            EntityKind::Wall => SpriteName::Forest,
+            // never sent by the server
+            EntityKind::Effect => SpriteName::None,
+        }
+    }
+
+    /// collision radius as a fraction of the sprite's scale
+    pub fn radius_multiplier(self) -> f32 {
+        match self {
+            // the blob is much smaller than its sprite
+            EntityKind::PlayerProjectile => 0.5,
+            EntityKind::Tile
+            | EntityKind::Forest
+            | EntityKind::Player
+            | EntityKind::Enemy
+            | EntityKind::Effect
+            | EntityKind::Pickup
+            | EntityKind::Wall => 1.0,
+        }
+    }
+
+    /// draw order, higher layers are drawn on top
+    pub fn layer(self) -> u8 {
+        match self {
+            EntityKind::Tile => 0,
+            EntityKind::PlayerProjectile => 1,
+            EntityKind::Enemy => 2,
+            EntityKind::Player => 3,
+            EntityKind::Forest => 4,
+            EntityKind::Effect => 5,
+            EntityKind::Pickup => 1,
+            EntityKind::Wall => 4,
+        }
+    }
+}
+
+/// the kind byte comes off the wire, so it's checked rather than transmuted
+impl TryFrom<u8> for EntityKind {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|&kind| kind as u8 == value)
+            .ok_or(Error::BadData)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct EntitySpawn {
+    /// the server's id for the entity, stable until it is destroyed and never reused
     pub id: i32,
     pub kind: EntityKind,
     pub pos: Vec2,
@@ -59,6 +241,11 @@ pub struct EntitySpawn {
     pub dir: Vec2,
 }
 
+impl EntitySpawn {
+    /// id + kind + pos + scale + speed + dir
+    pub const SIZE: usize = 29;
+}
+
 impl TryFrom<Packet> for EntitySpawn {
     type Error = Error;
     fn try_from(value: Packet) -> Result<Self> {
@@ -66,8 +253,11 @@ impl TryFrom<Packet> for EntitySpawn {
             Err(Error::BadOpcode)
         } else {
             let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
             let id = i32::from_be_bytes(data[0..4].try_into().unwrap());
-            let kind = unsafe { std::mem::transmute(data[4]) };
+            let kind = EntityKind::try_from(data[4])?;
             let x = f32::from_be_bytes(data[5..9].try_into().unwrap());
             let y = f32::from_be_bytes(data[9..13].try_into().unwrap());
             let scale = f32::from_be_bytes(data[13..17].try_into().unwrap());
@@ -88,7 +278,8 @@ impl TryFrom<Packet> for EntitySpawn {
 
 impl From<EntitySpawn> for Packet {
     fn from(value: EntitySpawn) -> Self {
-        let mut data = Vec::new();
+        let mut packet = Self::with_capacity(OpCode::EntitySpawn, EntitySpawn::SIZE);
+        let data = &mut packet.data;
         data.extend_from_slice(&value.id.to_be_bytes());
         data.extend_from_slice(&(value.kind as u8).to_be_bytes());
         data.extend_from_slice(&value.pos.x.to_be_bytes());
@@ -97,10 +288,7 @@ impl From<EntitySpawn> for Packet {
         data.extend_from_slice(&value.speed.to_be_bytes());
         data.extend_from_slice(&value.dir.x.to_be_bytes());
         data.extend_from_slice(&value.dir.y.to_be_bytes());
-        Self {
-            opcode: OpCode::EntitySpawn as u8 as _,
-            data,
-        }
+        packet
     }
 }
 
@@ -108,6 +296,26 @@ impl From<EntitySpawn> for Packet {
 pub struct EntityUpdate {
     pub id: i32,
     pub pos: Vec2,
+    pub rotation: f32,
+}
+
+impl EntityUpdate {
+    /// id + pos + rotation
+    pub const SIZE: usize = 16;
+
+    /// appends the packet as it goes on the wire, the same bytes as
+    /// `Packet::from(update).into_bytes()` without allocating
+    pub fn write_into(&self, buf: &mut Vec<u8>) {
+        buf.push(OpCode::EntityUpdate.into());
+        self.write_payload(buf);
+    }
+
+    fn write_payload(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.id.to_be_bytes());
+        data.extend_from_slice(&self.pos.x.to_be_bytes());
+        data.extend_from_slice(&self.pos.y.to_be_bytes());
+        data.extend_from_slice(&self.rotation.to_be_bytes());
+    }
 }
 
 impl TryFrom<Packet> for EntityUpdate {
@@ -117,12 +325,17 @@ impl TryFrom<Packet> for EntityUpdate {
             Err(Error::BadOpcode)
         } else {
             let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
             let id = i32::from_be_bytes(data[0..4].try_into().unwrap());
             let x = f32::from_be_bytes(data[4..8].try_into().unwrap());
             let y = f32::from_be_bytes(data[8..12].try_into().unwrap());
+            let rotation = f32::from_be_bytes(data[12..16].try_into().unwrap());
             Ok(Self {
                 id,
                 pos: Vec2::new(x, y),
+                rotation,
             })
         }
     }
@@ -130,14 +343,9 @@ impl TryFrom<Packet> for EntityUpdate {
 
 impl From<EntityUpdate> for Packet {
     fn from(value: EntityUpdate) -> Self {
-        let mut data = Vec::new();
-        data.extend_from_slice(&value.id.to_be_bytes());
-        data.extend_from_slice(&value.pos.x.to_be_bytes());
-        data.extend_from_slice(&value.pos.y.to_be_bytes());
-        Self {
-            opcode: OpCode::EntityUpdate as u8 as _,
-            data,
-        }
+        let mut packet = Self::with_capacity(OpCode::EntityUpdate, EntityUpdate::SIZE);
+        value.write_payload(&mut packet.data);
+        packet
     }
 }
 
@@ -146,6 +354,11 @@ pub struct EntityDestroy {
     pub id: i32,
 }
 
+impl EntityDestroy {
+    /// id
+    pub const SIZE: usize = 4;
+}
+
 impl TryFrom<Packet> for EntityDestroy {
     type Error = Error;
     fn try_from(value: Packet) -> Result<Self> {
@@ -153,6 +366,9 @@ impl TryFrom<Packet> for EntityDestroy {
             Err(Error::BadOpcode)
         } else {
             let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
             let id = i32::from_be_bytes(data[0..4].try_into().unwrap());
             Ok(Self { id })
         }
@@ -161,11 +377,860 @@ impl TryFrom<Packet> for EntityDestroy {
 
 impl From<EntityDestroy> for Packet {
     fn from(value: EntityDestroy) -> Self {
-        let mut data = Vec::new();
+        let mut packet = Self::with_capacity(OpCode::EntityDestroy, EntityDestroy::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.id.to_be_bytes());
+        packet
+    }
+}
+
+/// sent by the client once it has applied an EntitySpawn
+#[derive(Debug, Clone, Copy)]
+pub struct SpawnAck {
+    pub id: i32,
+}
+
+impl SpawnAck {
+    /// id
+    pub const SIZE: usize = 4;
+}
+
+impl TryFrom<Packet> for SpawnAck {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::SpawnAck != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let id = i32::from_be_bytes(data[0..4].try_into().unwrap());
+            Ok(Self { id })
+        }
+    }
+}
+
+impl From<SpawnAck> for Packet {
+    fn from(value: SpawnAck) -> Self {
+        let mut packet = Self::with_capacity(OpCode::SpawnAck, SpawnAck::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.id.to_be_bytes());
+        packet
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EntityListing {
+    pub id: i32,
+    pub kind: EntityKind,
+    pub pos: Vec2,
+}
+
+/// one chunk of the server's entity list
+#[derive(Debug, Clone)]
+pub struct EntityList {
+    pub entries: Vec<EntityListing>,
+}
+
+impl EntityList {
+    /// id + kind + pos
+    pub const ENTRY_SIZE: usize = 13;
+    /// as many entries as fit in a received packet after the opcode and count
+    pub const MAX_ENTRIES: usize = (socket::MAX_PACKET_SIZE - 2) / Self::ENTRY_SIZE;
+}
+
+impl TryFrom<Packet> for EntityList {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::EntityList != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            let count = *data.first().ok_or(Error::NotEnoughData)? as usize;
+            let entries = data[1..]
+                .chunks_exact(Self::ENTRY_SIZE)
+                .take(count)
+                .map(|e| {
+                    let id = i32::from_be_bytes(e[0..4].try_into().unwrap());
+                    let kind = EntityKind::try_from(e[4])?;
+                    let x = f32::from_be_bytes(e[5..9].try_into().unwrap());
+                    let y = f32::from_be_bytes(e[9..13].try_into().unwrap());
+                    Ok(EntityListing {
+                        id,
+                        kind,
+                        pos: Vec2::new(x, y),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if entries.len() != count {
+                Err(Error::NotEnoughData)
+            } else {
+                Ok(Self { entries })
+            }
+        }
+    }
+}
+
+impl From<EntityList> for Packet {
+    fn from(value: EntityList) -> Self {
+        let size = 1 + value.entries.len() * EntityList::ENTRY_SIZE;
+        let mut packet = Self::with_capacity(OpCode::EntityList, size);
+        let data = &mut packet.data;
+        data.push(value.entries.len() as u8);
+        for e in value.entries {
+            data.extend_from_slice(&e.id.to_be_bytes());
+            data.extend_from_slice(&(e.kind as u8).to_be_bytes());
+            data.extend_from_slice(&e.pos.x.to_be_bytes());
+            data.extend_from_slice(&e.pos.y.to_be_bytes());
+        }
+        packet
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Collision {
+    pub a: i32,
+    pub b: i32,
+    pub point: Vec2,
+}
+
+impl Collision {
+    /// a + b + point
+    pub const SIZE: usize = 16;
+}
+
+impl TryFrom<Packet> for Collision {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::Collision != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let a = i32::from_be_bytes(data[0..4].try_into().unwrap());
+            let b = i32::from_be_bytes(data[4..8].try_into().unwrap());
+            let x = f32::from_be_bytes(data[8..12].try_into().unwrap());
+            let y = f32::from_be_bytes(data[12..16].try_into().unwrap());
+            Ok(Self {
+                a,
+                b,
+                point: Vec2::new(x, y),
+            })
+        }
+    }
+}
+
+impl From<Collision> for Packet {
+    fn from(value: Collision) -> Self {
+        let mut packet = Self::with_capacity(OpCode::Collision, Collision::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.a.to_be_bytes());
+        data.extend_from_slice(&value.b.to_be_bytes());
+        data.extend_from_slice(&value.point.x.to_be_bytes());
+        data.extend_from_slice(&value.point.y.to_be_bytes());
+        packet
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverReason {
+    /// a hunter made it to the middle of the forest
+    BaseDestroyed,
+    /// every deer is down, players can't die yet
+    AllPlayersDead,
+}
+
+impl GameOverReason {
+    pub const ALL: [GameOverReason; 2] = [
+        GameOverReason::BaseDestroyed,
+        GameOverReason::AllPlayersDead,
+    ];
+}
+
+impl TryFrom<u8> for GameOverReason {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|&reason| reason as u8 == value)
+            .ok_or(Error::BadData)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GameOver {
+    pub reason: GameOverReason,
+}
+
+impl GameOver {
+    /// reason
+    pub const SIZE: usize = 1;
+}
+
+impl TryFrom<Packet> for GameOver {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::GameOver != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let reason = GameOverReason::try_from(data[0])?;
+            Ok(Self { reason })
+        }
+    }
+}
+
+impl From<GameOver> for Packet {
+    fn from(value: GameOver) -> Self {
+        let mut packet = Self::with_capacity(OpCode::GameOver, GameOver::SIZE);
+        packet.data.push(value.reason as u8);
+        packet
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Powerup {
+    /// shorter shot cooldown
+    RapidFire,
+    /// spit comes out in a fan
+    SpreadShot,
+    /// spit stuns hunters instead of splattering them
+    StunShot,
+    /// spit curves toward hunters ahead of it
+    HomingShot,
+}
+
+impl Powerup {
+    pub const ALL: [Powerup; 4] = [
+        Powerup::RapidFire,
+        Powerup::SpreadShot,
+        Powerup::StunShot,
+        Powerup::HomingShot,
+    ];
+}
+
+impl TryFrom<u8> for Powerup {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|&powerup| powerup as u8 == value)
+            .ok_or(Error::BadData)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PowerupApplied {
+    pub powerup: Powerup,
+    pub duration: Duration,
+}
+
+impl PowerupApplied {
+    /// powerup + duration in milliseconds
+    pub const SIZE: usize = 5;
+}
+
+impl TryFrom<Packet> for PowerupApplied {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::PowerupApplied != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let powerup = Powerup::try_from(data[0])?;
+            let millis = u32::from_be_bytes(data[1..5].try_into().unwrap());
+            Ok(Self {
+                powerup,
+                duration: Duration::from_millis(millis as _),
+            })
+        }
+    }
+}
+
+impl From<PowerupApplied> for Packet {
+    fn from(value: PowerupApplied) -> Self {
+        let mut packet = Self::with_capacity(OpCode::PowerupApplied, PowerupApplied::SIZE);
+        let data = &mut packet.data;
+        data.push(value.powerup as u8);
+        data.extend_from_slice(&(value.duration.as_millis() as u32).to_be_bytes());
+        packet
+    }
+}
+
+/// the server spits in `dir` for the sender, once per allowed shot
+#[derive(Debug, Clone, Copy)]
+pub struct Fire {
+    /// picked by the client, spits of a spread shot count up from it
+    pub correlation: u16,
+    pub dir: Vec2,
+}
+
+impl Fire {
+    /// correlation + dir
+    pub const SIZE: usize = 10;
+}
+
+impl TryFrom<Packet> for Fire {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::Fire != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let correlation = u16::from_be_bytes(data[0..2].try_into().unwrap());
+            let x = f32::from_be_bytes(data[2..6].try_into().unwrap());
+            let y = f32::from_be_bytes(data[6..10].try_into().unwrap());
+            Ok(Self {
+                correlation,
+                dir: Vec2::new(x, y),
+            })
+        }
+    }
+}
+
+impl From<Fire> for Packet {
+    fn from(value: Fire) -> Self {
+        let mut packet = Self::with_capacity(OpCode::Fire, Fire::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.correlation.to_be_bytes());
+        data.extend_from_slice(&value.dir.x.to_be_bytes());
+        data.extend_from_slice(&value.dir.y.to_be_bytes());
+        packet
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FireAck {
+    pub correlation: u16,
+    /// the server's id for the spit
+    pub id: i32,
+}
+
+impl FireAck {
+    /// correlation + id
+    pub const SIZE: usize = 6;
+}
+
+impl TryFrom<Packet> for FireAck {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::FireAck != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let correlation = u16::from_be_bytes(data[0..2].try_into().unwrap());
+            let id = i32::from_be_bytes(data[2..6].try_into().unwrap());
+            Ok(Self { correlation, id })
+        }
+    }
+}
+
+impl From<FireAck> for Packet {
+    fn from(value: FireAck) -> Self {
+        let mut packet = Self::with_capacity(OpCode::FireAck, FireAck::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.correlation.to_be_bytes());
         data.extend_from_slice(&value.id.to_be_bytes());
+        packet
+    }
+}
+
+/// sent in the Hello reply, so clients lay out the same tiles as the server without
+/// thousands of tile spawns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldInfo {
+    pub seed: u64,
+    /// tiles span -size..=size on both axes
+    pub size: isize,
+    /// clients plant the trees from the seed too, the server won't send them
+    pub local_statics: bool,
+}
+
+impl WorldInfo {
+    /// seed + size + local_statics
+    pub const SIZE: usize = 11;
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(Self::SIZE);
+        data.extend_from_slice(&self.seed.to_be_bytes());
+        data.extend_from_slice(&(self.size as u16).to_be_bytes());
+        data.push(self.local_statics as u8);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(Error::NotEnoughData);
+        }
+
+        let seed = u64::from_be_bytes(data[0..8].try_into().unwrap());
+        let size = u16::from_be_bytes(data[8..10].try_into().unwrap());
+        Ok(Self {
+            seed,
+            size: size as _,
+            local_statics: data[10] != 0,
+        })
+    }
+}
+
+/// the keys a player holds down, packed into one byte on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerInput {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+impl PlayerInput {
+    const UP: u8 = 1 << 0;
+    const DOWN: u8 = 1 << 1;
+    const LEFT: u8 = 1 << 2;
+    const RIGHT: u8 = 1 << 3;
+    const FIRE: u8 = 1 << 4;
+
+    pub fn to_bits(self) -> u8 {
+        let mut bits = 0;
+        for (held, flag) in [
+            (self.up, Self::UP),
+            (self.down, Self::DOWN),
+            (self.left, Self::LEFT),
+            (self.right, Self::RIGHT),
+            (self.fire, Self::FIRE),
+        ] {
+            if held {
+                bits |= flag;
+            }
+        }
+        bits
+    }
+
+    /// unnormalized, zero when nothing or opposite keys are held
+    pub fn dir(self) -> Vec2 {
+        let x = self.right as i32 - self.left as i32;
+        let y = self.up as i32 - self.down as i32;
+        Vec2::new(x as f32, y as f32)
+    }
+
+    /// unknown bits are ignored
+    pub fn from_bits(bits: u8) -> Self {
         Self {
-            opcode: OpCode::EntityDestroy as u8 as _,
-            data,
+            up: bits & Self::UP != 0,
+            down: bits & Self::DOWN != 0,
+            left: bits & Self::LEFT != 0,
+            right: bits & Self::RIGHT != 0,
+            fire: bits & Self::FIRE != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Input {
+    /// counts up once per client frame, so the server can drop inputs that arrive out of order
+    pub seq: u32,
+    pub input: PlayerInput,
+}
+
+impl Input {
+    /// seq + input flags
+    pub const SIZE: usize = 5;
+}
+
+impl TryFrom<Packet> for Input {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::Input != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let seq = u32::from_be_bytes(data[0..4].try_into().unwrap());
+            let input = PlayerInput::from_bits(data[4]);
+            Ok(Self { seq, input })
+        }
+    }
+}
+
+impl From<Input> for Packet {
+    fn from(value: Input) -> Self {
+        let mut packet = Self::with_capacity(OpCode::Input, Input::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.seq.to_be_bytes());
+        data.push(value.input.to_bits());
+        packet
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stun {
+    pub id: i32,
+    /// zero once the stun wears off
+    pub duration: Duration,
+}
+
+impl Stun {
+    /// id + duration in milliseconds
+    pub const SIZE: usize = 8;
+}
+
+impl TryFrom<Packet> for Stun {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::Stun != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let id = i32::from_be_bytes(data[0..4].try_into().unwrap());
+            let millis = u32::from_be_bytes(data[4..8].try_into().unwrap());
+            Ok(Self {
+                id,
+                duration: Duration::from_millis(millis as _),
+            })
+        }
+    }
+}
+
+impl From<Stun> for Packet {
+    fn from(value: Stun) -> Self {
+        let mut packet = Self::with_capacity(OpCode::Stun, Stun::SIZE);
+        let data = &mut packet.data;
+        data.extend_from_slice(&value.id.to_be_bytes());
+        data.extend_from_slice(&(value.duration.as_millis() as u32).to_be_bytes());
+        packet
+    }
+}
+
+/// picked by the client once per run, so the server can recognise it from a new address
+#[derive(Debug, Clone, Copy)]
+pub struct Identify {
+    pub token: u64,
+}
+
+impl Identify {
+    /// token
+    pub const SIZE: usize = 8;
+}
+
+impl TryFrom<Packet> for Identify {
+    type Error = Error;
+    fn try_from(value: Packet) -> Result<Self> {
+        if OpCode::Identify != value.opcode() {
+            Err(Error::BadOpcode)
+        } else {
+            let data = value.data();
+            if data.len() < Self::SIZE {
+                return Err(Error::NotEnoughData);
+            }
+            let token = u64::from_be_bytes(data[0..8].try_into().unwrap());
+            Ok(Self { token })
+        }
+    }
+}
+
+impl From<Identify> for Packet {
+    fn from(value: Identify) -> Self {
+        let mut packet = Self::with_capacity(OpCode::Identify, Identify::SIZE);
+        packet.data.extend_from_slice(&value.token.to_be_bytes());
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entity_kinds_round_trip_through_their_byte() {
+        for kind in EntityKind::ALL {
+            assert_eq!(EntityKind::try_from(kind as u8).unwrap(), kind);
+        }
+        let unknown = EntityKind::ALL.len() as u8;
+        assert!(matches!(EntityKind::try_from(unknown), Err(Error::BadData)));
+    }
+
+    #[test]
+    fn every_player_input_round_trips() {
+        for bits in 0..32u8 {
+            let input = PlayerInput::from_bits(bits);
+            assert_eq!(input.to_bits(), bits);
+            // unknown bits are dropped
+            assert_eq!(PlayerInput::from_bits(bits | 0xe0).to_bits(), bits);
+
+            let packet = Packet::from(Input { seq: 7, input });
+            let decoded = Input::try_from(packet).unwrap();
+            assert_eq!(decoded.seq, 7);
+            assert_eq!(decoded.input.to_bits(), bits);
+        }
+    }
+
+    #[test]
+    fn fixed_size_packets_fill_exactly_their_size() {
+        let v = Vec2::new(1.0, 2.0);
+        let packets: [(Packet, usize); 11] = [
+            (
+                EntitySpawn {
+                    id: 1,
+                    kind: EntityKind::Enemy,
+                    pos: v,
+                    scale: 1.0,
+                    speed: 2.0,
+                    dir: v,
+                }
+                .into(),
+                EntitySpawn::SIZE,
+            ),
+            (
+                EntityUpdate {
+                    id: 1,
+                    pos: v,
+                    rotation: 0.0,
+                }
+                .into(),
+                EntityUpdate::SIZE,
+            ),
+            (EntityDestroy { id: 1 }.into(), EntityDestroy::SIZE),
+            (SpawnAck { id: 1 }.into(), SpawnAck::SIZE),
+            (
+                Collision {
+                    a: 1,
+                    b: 2,
+                    point: v,
+                }
+                .into(),
+                Collision::SIZE,
+            ),
+            (
+                GameOver {
+                    reason: GameOverReason::BaseDestroyed,
+                }
+                .into(),
+                GameOver::SIZE,
+            ),
+            (
+                PowerupApplied {
+                    powerup: Powerup::HomingShot,
+                    duration: Duration::from_secs(3),
+                }
+                .into(),
+                PowerupApplied::SIZE,
+            ),
+            (
+                Fire {
+                    correlation: 1,
+                    dir: v,
+                }
+                .into(),
+                Fire::SIZE,
+            ),
+            (
+                FireAck {
+                    correlation: 1,
+                    id: 1,
+                }
+                .into(),
+                FireAck::SIZE,
+            ),
+            (
+                Stun {
+                    id: 1,
+                    duration: Duration::from_secs(1),
+                }
+                .into(),
+                Stun::SIZE,
+            ),
+            (Identify { token: 1 }.into(), Identify::SIZE),
+        ];
+
+        for (packet, size) in packets {
+            let op = packet.opcode::<u8>();
+            assert_eq!(packet.data().len(), size, "opcode {op}");
+        }
+        let input = Input {
+            seq: 1,
+            input: PlayerInput::default(),
+        };
+        assert_eq!(Packet::from(input).data().len(), Input::SIZE);
+        let world = WorldInfo {
+            seed: 1,
+            size: 2,
+            local_statics: true,
+        };
+        assert_eq!(world.to_bytes().len(), WorldInfo::SIZE);
+    }
+
+    #[test]
+    fn write_into_matches_the_packet_bytes() {
+        let updates = [
+            EntityUpdate {
+                id: 3,
+                pos: Vec2::new(1.5, -2.0),
+                rotation: 90.0,
+            },
+            EntityUpdate {
+                id: -1,
+                pos: Vec2::new(0.0, 7.25),
+                rotation: 0.0,
+            },
+        ];
+
+        let mut buf = Vec::new();
+        for update in updates {
+            buf.clear();
+            update.write_into(&mut buf);
+            assert_eq!(buf, Packet::from(update).into_bytes().unwrap());
+        }
+
+        // it appends, so updates can be batched into one buffer
+        buf.clear();
+        updates.iter().for_each(|u| u.write_into(&mut buf));
+        assert_eq!(buf.len(), 2 * (1 + EntityUpdate::SIZE));
+    }
+
+    #[test]
+    fn unknown_game_over_reasons_are_bad_data() {
+        for reason in GameOverReason::ALL {
+            let packet = Packet::from(GameOver { reason });
+            assert_eq!(GameOver::try_from(packet).unwrap().reason, reason);
+        }
+        let unknown = GameOverReason::ALL.len() as u8;
+        let packet = Packet::new(OpCode::GameOver, vec![unknown]);
+        assert!(matches!(GameOver::try_from(packet), Err(Error::BadData)));
+    }
+
+    #[test]
+    fn unknown_powerups_are_bad_data() {
+        for powerup in Powerup::ALL {
+            assert_eq!(Powerup::try_from(powerup as u8).unwrap(), powerup);
+        }
+        let mut data = vec![Powerup::ALL.len() as u8];
+        data.extend_from_slice(&1000u32.to_be_bytes());
+        let packet = Packet::new(OpCode::PowerupApplied, data);
+        assert!(matches!(
+            PowerupApplied::try_from(packet),
+            Err(Error::BadData)
+        ));
+    }
+
+    fn one_byte_short<T>(value: T) -> Result<T>
+    where
+        T: Into<Packet> + TryFrom<Packet, Error = Error>,
+    {
+        let packet: Packet = value.into();
+        let op = packet.opcode::<u8>();
+        let mut data = packet.data();
+        data.pop();
+        T::try_from(Packet::new(op, data))
+    }
+
+    #[test]
+    fn short_packets_are_not_enough_data() {
+        let v = Vec2::new(1.0, 2.0);
+        let short = [
+            one_byte_short(EntitySpawn {
+                id: 1,
+                kind: EntityKind::Enemy,
+                pos: v,
+                scale: 1.0,
+                speed: 2.0,
+                dir: v,
+            })
+            .err(),
+            one_byte_short(EntityUpdate {
+                id: 1,
+                pos: v,
+                rotation: 0.0,
+            })
+            .err(),
+            one_byte_short(EntityDestroy { id: 1 }).err(),
+            one_byte_short(SpawnAck { id: 1 }).err(),
+            one_byte_short(Collision {
+                a: 1,
+                b: 2,
+                point: v,
+            })
+            .err(),
+            one_byte_short(GameOver {
+                reason: GameOverReason::BaseDestroyed,
+            })
+            .err(),
+            one_byte_short(PowerupApplied {
+                powerup: Powerup::HomingShot,
+                duration: Duration::from_secs(3),
+            })
+            .err(),
+            one_byte_short(Fire {
+                correlation: 1,
+                dir: v,
+            })
+            .err(),
+            one_byte_short(FireAck {
+                correlation: 1,
+                id: 1,
+            })
+            .err(),
+            one_byte_short(Input {
+                seq: 1,
+                input: PlayerInput::default(),
+            })
+            .err(),
+            one_byte_short(Stun {
+                id: 1,
+                duration: Duration::from_secs(1),
+            })
+            .err(),
+            one_byte_short(Identify { token: 1 }).err(),
+        ];
+        for (i, err) in short.into_iter().enumerate() {
+            assert!(matches!(err, Some(Error::NotEnoughData)), "packet {i}");
+        }
+
+        let empty_list = Packet::new(OpCode::EntityList, socket::NoData);
+        assert!(matches!(
+            EntityList::try_from(empty_list),
+            Err(Error::NotEnoughData)
+        ));
+        assert!(matches!(
+            WorldInfo::from_bytes(&[0; WorldInfo::SIZE - 1]),
+            Err(Error::NotEnoughData)
+        ));
+    }
+
+    #[test]
+    fn entity_updates_round_trip_their_rotation() {
+        for rotation in [0.0, 90.0, -135.5, 359.75] {
+            let update = EntityUpdate {
+                id: 42,
+                pos: Vec2::new(-3.5, 8.0),
+                rotation,
+            };
+            let decoded = EntityUpdate::try_from(Packet::from(update)).unwrap();
+            assert_eq!(decoded.id, 42);
+            assert_eq!((decoded.pos.x, decoded.pos.y), (-3.5, 8.0));
+            assert_eq!(decoded.rotation, rotation);
         }
     }
 }
@@ -0,0 +1,188 @@
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 7777;
+
+static CONFIG: OnceLock<GameConfig> = OnceLock::new();
+
+/// accepts `ip` or `ip:port`
+pub fn parse_address(s: &str) -> Option<SocketAddr> {
+    s.parse().ok().or_else(|| {
+        let ip = s.parse::<IpAddr>().ok()?;
+        Some((ip, DEFAULT_PORT).into())
+    })
+}
+
+/// a timeout should cover at least this many pings, so one lost ping doesn't drop anyone
+const MIN_PINGS_PER_TIMEOUT: u32 = 2;
+
+/// GameLoop::setup can't take arguments, so main hands the config over here
+pub fn init(config: GameConfig) {
+    CONFIG.set(config).expect("config initialized twice");
+}
+
+pub fn get() -> GameConfig {
+    CONFIG.get().copied().unwrap_or_default()
+}
+
+/// how the deer spits
+#[derive(Debug, Clone, Copy)]
+pub struct WeaponConfig {
+    /// time between shots without powerups
+    pub cooldown: Duration,
+    pub projectile_speed: f32,
+    pub projectile_scale: f32,
+    /// how long spit flies before it is destroyed
+    pub projectile_ttl: Duration,
+    /// most spit one deer can have in the air, shots that would go over are dropped
+    /// the default fits rapid spread fire for a whole projectile_ttl
+    pub max_live_projectiles: usize,
+}
+
+impl Default for WeaponConfig {
+    fn default() -> Self {
+        Self {
+            cooldown: Duration::from_millis(250),
+            projectile_speed: 30.0,
+            projectile_scale: 6.0,
+            projectile_ttl: Duration::from_secs(3),
+            max_live_projectiles: 72,
+        }
+    }
+}
+
+/// how the hunters move
+#[derive(Debug, Clone, Copy)]
+pub struct EnemyConfig {
+    pub speed: f32,
+    pub scale: f32,
+    /// 1 walks straight at the base, 0 wanders around at random
+    pub aggression: f32,
+}
+
+impl Default for EnemyConfig {
+    fn default() -> Self {
+        Self {
+            speed: 24.0,
+            scale: 5.25,
+            aggression: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GameConfig {
+    /// where the client connects, and the port a hosted server listens on
+    pub server_address: SocketAddr,
+    /// most hunters allowed alive at once, spawning is skipped at the cap
+    pub max_enemies: usize,
+    /// periodically print a ServerStats summary
+    pub log_stats: bool,
+    /// may query the entity list in release builds
+    pub admin: Option<IpAddr>,
+    /// how often the server sends positions of the entities it simulates
+    pub entity_update_interval: Duration,
+    /// entities that moved less than this since their last update aren't resent
+    pub update_dead_zone: f32,
+    /// seconds without updates before a remote entity is frozen as stale
+    pub stale_after: f32,
+    /// dead reckon entities other clients own between their updates
+    pub simulate_remote: bool,
+    /// extra connection attempts before giving up, the wait doubles each time
+    pub connect_retries: u32,
+    /// longest frame the client will simulate in one step
+    pub max_dt: Duration,
+    /// number of frames dt is averaged over
+    pub dt_smoothing: usize,
+    /// client and server must agree on this or predicted spit won't line up
+    pub weapon: WeaponConfig,
+    pub enemy: EnemyConfig,
+    /// start with the frame rate display on
+    pub show_fps: bool,
+    /// live entity count that gets logged as a likely leak, the forest alone is ~4k tiles
+    pub max_entities_warn: Option<usize>,
+    /// spit can hit other players' deer, never your own
+    pub friendly_fire: bool,
+    /// network targets buffered per entity, each one delays remote entities by about
+    /// an entity_update_interval in exchange for smoother motion
+    pub interpolation_depth: usize,
+    /// odds of a shot hunter dropping a pickup
+    pub pickup_chance: f64,
+    /// how long a picked up powerup lasts
+    pub powerup_duration: Duration,
+    /// dump the position of every moving entity this often, by server id, to diff the
+    /// server's log against a client's when they drift apart
+    pub position_log_interval: Option<Duration>,
+    /// how often each side pings the other, also what keeps an idle client from timing out
+    pub ping_interval: Duration,
+    /// overlapping deer get pushed apart by the server
+    pub player_collision: bool,
+    /// clients only get updates for entities this close to their deer, None sends everything
+    pub interest_radius: Option<f32>,
+    /// clients plant the trees from the world seed instead of the server sending them
+    pub local_statics: bool,
+    /// how long destroyed entities take to vanish on clients, zero removes them at once
+    pub despawn_fade: Duration,
+    /// map file the server builds its walls from instead of planting a random forest
+    pub map: Option<&'static str>,
+    /// how far the camera zooms out and in, 1 fits the whole world on screen
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            server_address: (Ipv4Addr::LOCALHOST, DEFAULT_PORT).into(),
+            max_enemies: 64,
+            log_stats: false,
+            admin: None,
+            entity_update_interval: Duration::from_millis(100),
+            update_dead_zone: 0.1,
+            stale_after: 1.0,
+            simulate_remote: true,
+            connect_retries: 4,
+            max_dt: Duration::from_millis(100),
+            dt_smoothing: 3,
+            weapon: WeaponConfig::default(),
+            enemy: EnemyConfig::default(),
+            show_fps: false,
+            max_entities_warn: Some(8192),
+            friendly_fire: false,
+            interpolation_depth: 1,
+            pickup_chance: 0.1,
+            powerup_duration: Duration::from_secs(8),
+            position_log_interval: None,
+            ping_interval: Duration::from_secs(1),
+            player_collision: true,
+            interest_radius: None,
+            local_statics: false,
+            despawn_fade: Duration::from_millis(200),
+            map: None,
+            min_zoom: 1.0,
+            max_zoom: 4.0,
+        }
+    }
+}
+
+impl GameConfig {
+    /// the shortest timeout that still covers a few pings
+    pub fn min_timeout(&self) -> Duration {
+        self.ping_interval * MIN_PINGS_PER_TIMEOUT
+    }
+
+    /// warns and returns false if `timeout` leaves too little room for pings to arrive
+    pub fn check_ping_interval(&self, timeout: Duration) -> bool {
+        let fits = self.min_timeout() <= timeout;
+        if !fits {
+            println!(
+                "config - ping interval {:?} is too long for a {:?} timeout",
+                self.ping_interval, timeout
+            );
+        }
+        fits
+    }
+}
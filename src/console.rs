@@ -0,0 +1,147 @@
+// Consoles
+// Reads commands from stdin and hands them to the game, operator commands on a dedicated
+// server and player commands on a client, which has no key bindings yet
+
+use std::io;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+use crate::config;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    /// list connected clients
+    Clients,
+    /// drop a client and its deer
+    Kick(SocketAddr),
+    /// kick every client from this ip and ignore it from now on
+    Ban(IpAddr),
+    /// spawn this many hunters at once
+    SpawnWave(usize),
+    /// print every entity as json lines
+    Dump,
+    /// shut the server down
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+        let cmd = match (command, arg) {
+            ("clients", None) => Command::Clients,
+            ("kick", Some(addr)) => {
+                Command::Kick(addr.parse().map_err(|_| format!("bad address: {addr}"))?)
+            }
+            ("ban", Some(ip)) => Command::Ban(ip.parse().map_err(|_| format!("bad ip: {ip}"))?),
+            ("spawn-wave", Some(n)) => {
+                Command::SpawnWave(n.parse().map_err(|_| format!("bad count: {n}"))?)
+            }
+            ("dump", None) => Command::Dump,
+            ("quit", None) => Command::Quit,
+            _ => return Err(format!("unknown command: {s}")),
+        };
+
+        Ok(cmd)
+    }
+}
+
+/// commands typed into a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientCommand {
+    /// join the server at this address, from the menu
+    Connect(SocketAddr),
+    /// vote to start the next wave
+    Ready,
+    /// show or hide the frame rate
+    Fps,
+    /// one zoom step closer
+    ZoomIn,
+    /// one zoom step further out
+    ZoomOut,
+    /// switch between following the deer and showing the whole world
+    #[cfg(debug_assertions)]
+    God,
+}
+
+impl FromStr for ClientCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+        let cmd = match (command, arg) {
+            ("connect", Some(addr)) => ClientCommand::Connect(
+                config::parse_address(addr).ok_or_else(|| format!("bad address: {addr}"))?,
+            ),
+            ("ready", None) => ClientCommand::Ready,
+            ("fps", None) => ClientCommand::Fps,
+            ("zoom", Some("in")) => ClientCommand::ZoomIn,
+            ("zoom", Some("out")) => ClientCommand::ZoomOut,
+            #[cfg(debug_assertions)]
+            ("god", None) => ClientCommand::God,
+            _ => return Err(format!("unknown command: {s}")),
+        };
+
+        Ok(cmd)
+    }
+}
+
+pub fn console_loop<C: FromStr<Err = String>>(tx: Sender<C>) {
+    for line in io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match line.parse() {
+            Ok(cmd) => {
+                if tx.send(cmd).is_err() {
+                    // whoever was listening is gone
+                    break;
+                }
+            }
+            Err(e) => println!("console - {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn connect_takes_an_ip_with_or_without_a_port() {
+        let parse = |s: &str| s.parse::<ClientCommand>();
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        assert_eq!(
+            parse("connect 10.0.0.2:9000"),
+            Ok(ClientCommand::Connect((ip, 9000).into()))
+        );
+        assert_eq!(
+            parse("connect 10.0.0.2"),
+            Ok(ClientCommand::Connect((ip, config::DEFAULT_PORT).into()))
+        );
+        assert!(parse("connect deer").is_err());
+        assert!(parse("connect").is_err());
+    }
+
+    #[test]
+    fn zoom_needs_a_direction() {
+        let parse = |s: &str| s.parse::<ClientCommand>();
+        assert_eq!(parse("zoom in"), Ok(ClientCommand::ZoomIn));
+        assert_eq!(parse("  zoom   out "), Ok(ClientCommand::ZoomOut));
+        assert!(parse("zoom").is_err());
+        assert!(parse("zoom sideways").is_err());
+    }
+}
@@ -1,90 +1,226 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::path::Path;
-use std::rc::Rc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use engine_2d::math::Mat3;
 use engine_2d::math::Vec2;
 use engine_2d::render::shader::Shader;
-use engine_2d::render::sprite::ISprite;
 use engine_2d::render::sprite::Sprite;
 use engine_2d::render::texture::ITexture;
 use engine_2d::render::texture::Texture;
+use engine_2d::time::Cooldown;
 use engine_2d::window::DrawContext;
-use rand::thread_rng;
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
+use rayon::prelude::*;
 
 use crate::common::EntityKind;
 use crate::common::EntitySpawn;
+use crate::common::Powerup;
+use crate::common::SpriteName;
+use crate::common::WorldInfo;
+use crate::math_ext::lerp_angle;
+use crate::math_ext::Vec2Ext;
+use crate::renderer::Renderer;
+use crate::renderer::SpriteRenderer;
+use crate::renderer::Transform;
 use crate::socket;
-use crate::SpriteName;
 
 pub const WORLD_SIZE: isize = 64;
 
-fn world() -> Mat3 {
-    // the world is going to be 128 * 128 tiles
-    // centered at 0,0
-    let scale = 1.0 / (WORLD_SIZE as f32);
-    Mat3::scale(Vec2::new(scale, scale))
+/// how quickly interpolated entities close the gap to their network target
+const INTERPOLATION_RATE: f32 = 15.0;
+/// squared distance at which a buffered network target counts as reached
+const TARGET_REACHED: f32 = 0.01;
+/// stale entities are drawn this much smaller so frozen ones stand out from idle ones
+const STALE_SCALE: f32 = 0.75;
+
+/// rings tried around a spawn point when looking for free space, and spots per ring
+const FREE_POSITION_RINGS: usize = 8;
+const FREE_POSITION_SAMPLES: usize = 12;
+
+/// how well the interpolation buffers are keeping up
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InterpolationStats {
+    /// network targets waiting to be eased towards, over all entities
+    pub buffered: usize,
+    /// times an entity reached its last target with nothing queued behind it
+    pub underruns: u32,
+}
+
+/// temporary entity state that wears off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// the sprite blinks after taking a hit
+    DamageFlash,
+    /// granted by a pickup
+    Powerup(Powerup),
+    /// can't move and blinks until it wears off
+    Stun,
+}
+
+fn countdown(duration: Duration) -> Cooldown {
+    let mut cooldown = Cooldown::new(duration);
+    cooldown.enable();
+    cooldown
 }
 
-pub trait Entity {
+/// Send so the manager can tick entities in parallel
+pub trait Entity: Send {
     fn pos(&self) -> Vec2;
+    /// where the last tick started, pos for anything that hasn't ticked yet
+    fn prev_pos(&self) -> Vec2;
     fn kind(&self) -> EntityKind;
     fn scale(&self) -> f32;
     fn speed(&self) -> f32;
     fn dir(&self) -> Vec2;
+    fn rotation(&self) -> f32;
+    /// id of the player entity this belongs to, None for the server's own entities
+    fn owner(&self) -> Option<i32>;
+    fn set_owner(&mut self, owner: Option<i32>);
+    /// unsimulated entities only move towards network targets
+    fn set_simulated(&mut self, simulated: bool);
+
+    /// sprites are drawn on a quad spanning [-scale, scale], the hitbox is scaled per kind
+    fn radius(&self) -> f32 {
+        self.scale() * self.kind().radius_multiplier()
+    }
 
     fn set_pos(&mut self, pos: Vec2);
     fn set_direction(&mut self, dir: Vec2);
+    fn set_rotation(&mut self, rotation: f32);
+    /// the position the server says this entity is at
+    fn set_network_target(&mut self, pos: Vec2);
+    /// the facing the server says this entity has
+    fn set_network_rotation(&mut self, rotation: f32);
+    /// how many network targets are queued before the oldest is dropped
+    fn set_interpolation_depth(&mut self, depth: usize);
+    fn interpolation_stats(&self) -> InterpolationStats;
+    /// seconds since the last network target, None if the network never moved it
+    fn since_update(&self) -> Option<f32>;
+    /// stale entities stay frozen in place until the next network target
+    fn set_stale(&mut self, stale: bool);
+    fn is_stale(&self) -> bool;
 
     fn kill(&mut self);
     fn is_alive(&self) -> bool;
+    /// the entity is destroyed `ttl` from now
+    fn set_ttl(&mut self, ttl: Duration);
+    fn expired(&self) -> bool;
+    /// stops where it is and shrinks away over `duration`, then expires
+    fn fade_out(&mut self, duration: Duration);
+
+    fn add_effect(&mut self, effect: Effect, duration: Duration);
+    fn has_effect(&self, effect: Effect) -> bool;
+    /// ends an effect early, it isn't reported as expired
+    fn remove_effect(&mut self, effect: Effect);
+    /// effects that wore off since the last call, each is reported once
+    fn take_expired_effects(&mut self) -> Vec<Effect>;
+
+    /// debug output names the entity this instead of its kind and id
+    #[cfg(debug_assertions)]
+    fn set_label(&mut self, label: String);
+    #[cfg(debug_assertions)]
+    fn label(&self) -> Option<&str>;
 
     fn tick(&mut self, dt: f32) -> bool;
-    fn render(&self, shader: &Shader);
+    fn render(&self, renderer: &mut dyn Renderer, view: Mat3);
 }
 
-pub struct BaseEntity<'a> {
+pub struct BaseEntity {
     alive: bool,
+    ttl: Option<Cooldown>,
+    expired: bool,
+    effects: Vec<(Effect, Cooldown)>,
+    expired_effects: Vec<Effect>,
+    /// flips every tick while flashing
+    flash_hidden: bool,
+    /// sprites face right until the entity first moves left
+    facing_left: bool,
     pos: Vec2,
+    prev_pos: Vec2,
     scale: f32,
     speed: f32,
     rotation: f32,
     direction: Vec2,
-    sprite: Option<Rc<Sprite<'a>>>,
+    sprite: SpriteName,
     kind: EntityKind,
+    owner: Option<i32>,
+    simulated: bool,
+    /// locally controlled entities snap to network targets instead of easing
+    interpolated: bool,
+    /// eased towards front to back
+    targets: VecDeque<Vec2>,
+    interpolation_depth: usize,
+    /// the last target was reached with nothing queued behind it
+    starved: bool,
+    underruns: u32,
+    target_rotation: Option<f32>,
+    since_update: f32,
+    stale: bool,
+    /// seconds left of a fade out, and how long it lasts in total
+    fade: Option<(f32, f32)>,
+    #[cfg(debug_assertions)]
+    label: Option<String>,
 }
 
-impl<'a> BaseEntity<'a> {
+impl BaseEntity {
     pub fn new(
         pos: Vec2,
         scale: f32,
         speed: f32,
         rotation: f32,
         direction: Vec2,
-        sprite: Option<Rc<Sprite<'a>>>,
+        sprite: SpriteName,
         kind: EntityKind,
     ) -> Self {
         Self {
             alive: true,
+            ttl: None,
+            expired: false,
+            effects: Vec::new(),
+            expired_effects: Vec::new(),
+            flash_hidden: false,
+            facing_left: false,
             pos,
+            prev_pos: pos,
             scale,
             speed,
             rotation,
             direction,
             sprite,
             kind,
+            owner: None,
+            simulated: true,
+            interpolated: true,
+            targets: VecDeque::new(),
+            interpolation_depth: 1,
+            starved: false,
+            underruns: 0,
+            target_rotation: None,
+            since_update: 0.0,
+            stale: false,
+            fade: None,
+            #[cfg(debug_assertions)]
+            label: None,
         }
     }
 }
 
-impl<'a> Entity for BaseEntity<'a> {
+impl Entity for BaseEntity {
     fn pos(&self) -> Vec2 {
         self.pos
     }
 
+    fn prev_pos(&self) -> Vec2 {
+        self.prev_pos
+    }
+
     fn kind(&self) -> EntityKind {
         self.kind
     }
@@ -101,6 +237,22 @@ impl<'a> Entity for BaseEntity<'a> {
         self.direction
     }
 
+    fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    fn owner(&self) -> Option<i32> {
+        self.owner
+    }
+
+    fn set_owner(&mut self, owner: Option<i32>) {
+        self.owner = owner;
+    }
+
+    fn set_simulated(&mut self, simulated: bool) {
+        self.simulated = simulated;
+    }
+
     fn set_pos(&mut self, pos: Vec2) {
         self.pos = pos;
     }
@@ -109,6 +261,54 @@ impl<'a> Entity for BaseEntity<'a> {
         self.direction = dir;
     }
 
+    fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+    }
+
+    fn set_network_target(&mut self, pos: Vec2) {
+        if self.interpolated {
+            self.targets.push_back(pos);
+            while self.targets.len() > self.interpolation_depth {
+                self.targets.pop_front();
+            }
+        } else {
+            self.pos = pos;
+        }
+        self.since_update = 0.0;
+        self.stale = false;
+    }
+
+    fn set_network_rotation(&mut self, rotation: f32) {
+        if self.interpolated {
+            self.target_rotation = Some(rotation);
+        } else {
+            self.rotation = rotation;
+        }
+    }
+
+    fn set_interpolation_depth(&mut self, depth: usize) {
+        self.interpolation_depth = depth.max(1);
+    }
+
+    fn interpolation_stats(&self) -> InterpolationStats {
+        InterpolationStats {
+            buffered: self.targets.len(),
+            underruns: self.underruns,
+        }
+    }
+
+    fn since_update(&self) -> Option<f32> {
+        (!self.targets.is_empty()).then_some(self.since_update)
+    }
+
+    fn set_stale(&mut self, stale: bool) {
+        self.stale = stale;
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+
     fn kill(&mut self) {
         self.alive = false;
     }
@@ -117,43 +317,153 @@ impl<'a> Entity for BaseEntity<'a> {
         self.alive
     }
 
+    fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = Some(countdown(ttl));
+        self.expired = false;
+    }
+
+    fn expired(&self) -> bool {
+        self.expired
+    }
+
+    fn fade_out(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f32();
+        self.fade = Some((secs, secs));
+        self.set_ttl(duration);
+        self.direction = Vec2::default();
+        self.targets.clear();
+    }
+
+    fn add_effect(&mut self, effect: Effect, duration: Duration) {
+        // re-applying an effect restarts it
+        self.effects.retain(|(e, _)| *e != effect);
+        self.effects.push((effect, countdown(duration)));
+    }
+
+    fn has_effect(&self, effect: Effect) -> bool {
+        self.effects.iter().any(|(e, _)| *e == effect)
+    }
+
+    fn remove_effect(&mut self, effect: Effect) {
+        self.effects.retain(|(e, _)| *e != effect);
+    }
+
+    fn take_expired_effects(&mut self) -> Vec<Effect> {
+        std::mem::take(&mut self.expired_effects)
+    }
+
+    #[cfg(debug_assertions)]
+    fn set_label(&mut self, label: String) {
+        self.label = Some(label);
+    }
+
+    #[cfg(debug_assertions)]
+    fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
     fn tick(&mut self, dt: f32) -> bool {
-        let dpos = self.speed * self.direction.normalize();
-        self.pos += dt * dpos;
+        self.prev_pos = self.pos;
+        let step = Duration::from_secs_f32(dt);
+        if let Some(ttl) = self.ttl.as_mut() {
+            if ttl.tick(step) {
+                self.expired = true;
+                self.ttl = None;
+            }
+        }
+
+        let expired_effects = &mut self.expired_effects;
+        self.effects.retain_mut(|(effect, timer)| {
+            let done = timer.tick(step);
+            if done {
+                expired_effects.push(*effect);
+            }
+            !done
+        });
+        if let Some((left, _)) = self.fade.as_mut() {
+            *left = (*left - dt).max(0.0);
+        }
+        let flashing = self.has_effect(Effect::DamageFlash) || self.has_effect(Effect::Stun);
+        self.flash_hidden = flashing && !self.flash_hidden;
+
+        let old_x = self.pos.x;
+        if let Some(&target) = self.targets.front() {
+            self.since_update += dt;
+            if !self.stale {
+                let t = (dt * INTERPOLATION_RATE).min(1.0);
+                self.pos = self.pos.lerp(target, t);
+                if let Some(rotation) = self.target_rotation {
+                    self.rotation = lerp_angle(self.rotation, rotation, t);
+                }
+
+                if (target - self.pos).len2() < TARGET_REACHED {
+                    if self.targets.len() > 1 {
+                        self.targets.pop_front();
+                        self.starved = false;
+                    } else if !self.starved && self.interpolation_depth > 1 {
+                        // with a single target there's no buffer to run dry
+                        self.underruns += 1;
+                        self.starved = true;
+                    }
+                }
+            }
+        } else if self.simulated && self.direction.len2() > 0.0 && !self.has_effect(Effect::Stun) {
+            let dpos = self.speed * self.direction.normalize();
+            self.pos += dt * dpos;
+        }
+
+        // standing still or moving straight up/down keeps the last facing
+        let dx = self.pos.x - old_x;
+        if self.kind.flips() && dx.abs() > f32::EPSILON {
+            self.facing_left = dx < 0.0;
+        }
 
         let bound = (WORLD_SIZE as f32) * 1.5;
         -bound <= self.pos.x && self.pos.x <= bound && -bound <= self.pos.y && self.pos.y <= bound
     }
 
-    fn render(&self, shader: &Shader) {
-        if let Some(sprite) = self.sprite.clone() {
-            let sprite_matrix = Mat3::translate(Vec2::new(self.pos.x, self.pos.y))
-                * Mat3::rotate(self.rotation)
-                * Mat3::scale(Vec2::new(self.scale, self.scale));
-            sprite.draw(shader, world() * sprite_matrix);
+    fn render(&self, renderer: &mut dyn Renderer, view: Mat3) {
+        if self.flash_hidden || self.sprite == SpriteName::None {
+            return;
         }
+
+        // no alpha in the shader yet, so fading and stale sprites shrink instead of dimming
+        let fade = self.fade.map_or(1.0, |(left, total)| left / total);
+        let stale = if self.stale { STALE_SCALE } else { 1.0 };
+        let scale = fade * stale * self.scale;
+        let flip = if self.facing_left { -1.0 } else { 1.0 };
+        let transform = Transform {
+            pos: self.pos,
+            rotation: self.rotation,
+            scale: Vec2::new(flip * scale, scale),
+        };
+        renderer.draw(self.sprite, view, transform);
     }
 }
 
 pub type KeyEvent = (bool, bool, bool, bool);
 
-pub struct Player<'a> {
-    base: BaseEntity<'a>,
+pub struct Player {
+    base: BaseEntity,
     rx: Receiver<KeyEvent>,
     ptx: Sender<Vec2>,
 }
 
-impl<'a> Player<'a> {
-    pub fn new(base: BaseEntity<'a>, rx: Receiver<KeyEvent>, ptx: Sender<Vec2>) -> Self {
+impl Player {
+    pub fn new(base: BaseEntity, rx: Receiver<KeyEvent>, ptx: Sender<Vec2>) -> Self {
         Self { base, rx, ptx }
     }
 }
 
-impl<'a> Entity for Player<'a> {
+impl Entity for Player {
     fn pos(&self) -> Vec2 {
         self.base.pos
     }
 
+    fn prev_pos(&self) -> Vec2 {
+        self.base.prev_pos
+    }
+
     fn kind(&self) -> EntityKind {
         EntityKind::Player
     }
@@ -170,6 +480,22 @@ impl<'a> Entity for Player<'a> {
         self.base.direction
     }
 
+    fn rotation(&self) -> f32 {
+        self.base.rotation
+    }
+
+    fn owner(&self) -> Option<i32> {
+        self.base.owner()
+    }
+
+    fn set_owner(&mut self, owner: Option<i32>) {
+        self.base.set_owner(owner)
+    }
+
+    fn set_simulated(&mut self, simulated: bool) {
+        self.base.set_simulated(simulated)
+    }
+
     fn set_pos(&mut self, pos: Vec2) {
         self.base.set_pos(pos);
     }
@@ -177,6 +503,39 @@ impl<'a> Entity for Player<'a> {
     fn set_direction(&mut self, dir: Vec2) {
         self.base.set_direction(dir)
     }
+
+    fn set_rotation(&mut self, rotation: f32) {
+        self.base.set_rotation(rotation)
+    }
+
+    fn set_network_target(&mut self, pos: Vec2) {
+        self.base.set_network_target(pos)
+    }
+
+    fn set_network_rotation(&mut self, rotation: f32) {
+        self.base.set_network_rotation(rotation)
+    }
+
+    fn set_interpolation_depth(&mut self, depth: usize) {
+        self.base.set_interpolation_depth(depth)
+    }
+
+    fn interpolation_stats(&self) -> InterpolationStats {
+        self.base.interpolation_stats()
+    }
+
+    fn since_update(&self) -> Option<f32> {
+        self.base.since_update()
+    }
+
+    fn set_stale(&mut self, stale: bool) {
+        self.base.set_stale(stale)
+    }
+
+    fn is_stale(&self) -> bool {
+        self.base.is_stale()
+    }
+
     fn kill(&mut self) {
         self.base.kill()
     }
@@ -185,6 +544,44 @@ impl<'a> Entity for Player<'a> {
         self.base.is_alive()
     }
 
+    fn set_ttl(&mut self, ttl: Duration) {
+        self.base.set_ttl(ttl)
+    }
+
+    fn expired(&self) -> bool {
+        self.base.expired()
+    }
+
+    fn fade_out(&mut self, duration: Duration) {
+        self.base.fade_out(duration)
+    }
+
+    fn add_effect(&mut self, effect: Effect, duration: Duration) {
+        self.base.add_effect(effect, duration)
+    }
+
+    fn has_effect(&self, effect: Effect) -> bool {
+        self.base.has_effect(effect)
+    }
+
+    fn remove_effect(&mut self, effect: Effect) {
+        self.base.remove_effect(effect)
+    }
+
+    fn take_expired_effects(&mut self) -> Vec<Effect> {
+        self.base.take_expired_effects()
+    }
+
+    #[cfg(debug_assertions)]
+    fn set_label(&mut self, label: String) {
+        self.base.set_label(label)
+    }
+
+    #[cfg(debug_assertions)]
+    fn label(&self) -> Option<&str> {
+        self.base.label()
+    }
+
     fn tick(&mut self, dt: f32) -> bool {
         // let (w, a, s, d) = self.rx.recv().unwrap();
         let (w, a, s, d) = (false, false, false, false);
@@ -201,42 +598,306 @@ impl<'a> Entity for Player<'a> {
         true
     }
 
-    fn render(&self, shader: &Shader) {
-        self.base.render(shader);
+    fn render(&self, renderer: &mut dyn Renderer, view: Mat3) {
+        self.base.render(renderer, view);
+    }
+}
+
+/// called with the id and entity whenever one is spawned or destroyed
+pub type EntityHook<'e> = Box<dyn FnMut(i32, &dyn Entity) + 'e>;
+
+type Store<'e> = Vec<(i32, Box<dyn Entity + 'e>)>;
+
+/// where an entity lives, keyed by id
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Static(usize),
+    Dynamic(usize),
+}
+
+/// the entity's own label in debug builds, otherwise its kind and id
+fn label(id: i32, e: &dyn Entity) -> String {
+    #[cfg(debug_assertions)]
+    if let Some(label) = e.label() {
+        return label.to_owned();
+    }
+    format!("{:?}#{}", e.kind(), id)
+}
+
+/// quoted and escaped, labels are free text
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// json has no NaN or infinity, a broken value shows up as null
+fn json_number(v: f32) -> String {
+    if v.is_finite() {
+        format!("{:.3}", v)
+    } else {
+        "null".to_owned()
     }
 }
 
 #[derive(Default)]
 pub struct EntityManager<'e, 's: 'e> {
-    sprites: HashMap<SpriteName, Rc<Sprite<'s>>>,
-    entities: Vec<(i32, Box<dyn Entity + 'e>)>,
+    sprites: HashMap<SpriteName, Sprite<'s>>,
+    /// tiles and trees, never ticked
+    statics: Store<'e>,
+    /// everything that moves or expires
+    dynamics: Store<'e>,
+    /// ids are handed out in order and never reused, so an id names the same entity for
+    /// the manager's whole lifetime, even after it's gone.
+    /// clients rely on this to rebuild their server id mapping after a resync.
+    /// only live entities have a slot, and walking it goes in id, which is spawn, order
+    slots: BTreeMap<i32, Slot>,
     entity_counter: i32,
+    /// live entities of each kind, kept up to date on spawn and destroy
+    counts: HashMap<EntityKind, usize>,
+    spawn_hooks: Vec<EntityHook<'e>>,
+    despawn_hooks: Vec<EntityHook<'e>>,
+    /// when set, only entities owned by this player are simulated locally
+    local_owner: Option<i32>,
+    /// live entity count that gets logged as a likely leak
+    max_entities_warn: Option<usize>,
+    /// given to every entity on spawn, 0 leaves the entity's default
+    interpolation_depth: usize,
+    /// so crossing the threshold is only logged once
+    over_max_entities: bool,
 }
 
 impl<'e, 's: 'e> EntityManager<'e, 's> {
+    /// in spawn order, whichever store the entities are in
     pub fn iter(&self) -> impl Iterator<Item = (i32, &dyn Entity)> {
-        self.entities
-            .iter()
-            .filter(|e| e.1.is_alive())
+        self.slots
+            .values()
+            .map(|&slot| self.entry(slot))
             .map(|e| (e.0, e.1.as_ref()))
     }
 
+    fn entry(&self, slot: Slot) -> &(i32, Box<dyn Entity + 'e>) {
+        match slot {
+            Slot::Static(i) => &self.statics[i],
+            Slot::Dynamic(i) => &self.dynamics[i],
+        }
+    }
+
+    fn entry_mut(&mut self, slot: Slot) -> &mut (i32, Box<dyn Entity + 'e>) {
+        match slot {
+            Slot::Static(i) => &mut self.statics[i],
+            Slot::Dynamic(i) => &mut self.dynamics[i],
+        }
+    }
+
+    pub fn iter_kind(&self, kind: EntityKind) -> impl Iterator<Item = (i32, &dyn Entity)> {
+        self.iter().filter(move |e| e.1.kind() == kind)
+    }
+
+    pub fn iter_mut_kind(
+        &mut self,
+        kind: EntityKind,
+    ) -> impl Iterator<Item = (i32, &mut (dyn Entity + 'e))> {
+        let store = if kind.is_static() {
+            &mut self.statics
+        } else {
+            &mut self.dynamics
+        };
+        store
+            .iter_mut()
+            .filter(move |e| e.1.kind() == kind)
+            .map(|e| (e.0, e.1.as_mut()))
+    }
+
+    /// the entity on the highest layer covering `pos`, ties go to the one drawn last
+    pub fn topmost_at(&self, pos: Vec2) -> Option<i32> {
+        self.iter()
+            .filter(|(_, e)| e.pos().distance(pos) <= e.radius())
+            .max_by_key(|(_, e)| e.kind().layer())
+            .map(|(id, _)| id)
+    }
+
+    /// one json object per line for every live entity, in id order
+    /// numbers are rounded so a client's dump diffs cleanly against the server's
+    pub fn debug_dump(&self) -> String {
+        let mut dump = String::new();
+        for &slot in self.slots.values() {
+            let (id, e) = self.entry(slot);
+            let (pos, dir) = (e.pos(), e.dir());
+            dump += &format!(
+                concat!(
+                    "{{\"id\":{},\"label\":{},\"kind\":\"{:?}\",\"pos\":[{},{}],",
+                    "\"scale\":{},\"dir\":[{},{}]}}\n"
+                ),
+                id,
+                json_string(&label(*id, e.as_ref())),
+                e.kind(),
+                json_number(pos.x),
+                json_number(pos.y),
+                json_number(e.scale()),
+                json_number(dir.x),
+                json_number(dir.y)
+            );
+        }
+        dump
+    }
+
+    /// what debug output calls the entity
+    pub fn label(&self, id: i32) -> String {
+        let slot = self.slot(id).unwrap();
+        label(id, self.entry(slot).1.as_ref())
+    }
+
+    /// the spot closest to `near`, within `radius` of it, where something `clearance` big
+    /// wouldn't overlap anything but the floor
+    /// when every spot tried is taken, the one overlapping the fewest entities
+    pub fn find_free_position(&self, near: Vec2, radius: f32, clearance: f32) -> Vec2 {
+        let overlaps = |pos: Vec2| {
+            self.iter()
+                .filter(|(_, e)| !matches!(e.kind(), EntityKind::Tile | EntityKind::Effect))
+                .filter(|(_, e)| {
+                    let reach = e.radius() + clearance;
+                    (e.pos() - pos).len2() < reach * reach
+                })
+                .count()
+        };
+
+        let mut best = (overlaps(near), near);
+        for ring in 1..=FREE_POSITION_RINGS {
+            if best.0 == 0 {
+                break;
+            }
+            let distance = radius * ring as f32 / FREE_POSITION_RINGS as f32;
+            for sample in 0..FREE_POSITION_SAMPLES {
+                let angle = 360.0 * sample as f32 / FREE_POSITION_SAMPLES as f32;
+                let pos = near + distance * Vec2::new(1.0, 0.0).rotate(angle);
+                let count = overlaps(pos);
+                if count < best.0 {
+                    best = (count, pos);
+                }
+                if count == 0 {
+                    break;
+                }
+            }
+        }
+        best.1
+    }
+
+    /// the `n` live entities of `kind` closest to `center`, closest first
+    /// equally close entities come lowest id first, whatever order they're stored in
+    pub fn nearest_of_kind(&self, center: Vec2, kind: EntityKind, n: usize) -> Vec<(i32, Vec2)> {
+        let mut found = self
+            .iter_kind(kind)
+            .map(|(id, e)| (id, e.pos()))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| {
+            let da = (a.1 - center).len2();
+            let db = (b.1 - center).len2();
+            da.total_cmp(&db).then(a.0.cmp(&b.0))
+        });
+        found.truncate(n);
+        found
+    }
+
     pub fn load_sprite<'c: 's>(&mut self, ctx: &'c DrawContext, name: SpriteName, path: &Path) {
         self.sprites.insert(
             name,
-            Rc::new(Sprite::new(ctx, Texture::from_file(ctx, path).unwrap())),
+            Sprite::new(ctx, Texture::from_file(ctx, path).unwrap()),
         );
     }
 
-    fn emplace_entity(&mut self, entity: Box<dyn Entity + 'e>) -> i32 {
+    pub fn on_spawn(&mut self, hook: impl FnMut(i32, &dyn Entity) + 'e) {
+        self.spawn_hooks.push(Box::new(hook));
+    }
+
+    pub fn on_despawn(&mut self, hook: impl FnMut(i32, &dyn Entity) + 'e) {
+        self.despawn_hooks.push(Box::new(hook));
+    }
+
+    fn emplace_entity(&mut self, mut entity: Box<dyn Entity + 'e>) -> i32 {
+        if self.interpolation_depth > 0 {
+            entity.set_interpolation_depth(self.interpolation_depth);
+        }
         let id = self.entity_counter;
         self.entity_counter += 1;
 
-        self.entities.push((id, entity));
+        for hook in self.spawn_hooks.iter_mut() {
+            hook(id, entity.as_ref());
+        }
+
+        let kind = entity.kind();
+        let slot = if kind.is_static() {
+            self.statics.push((id, entity));
+            Slot::Static(self.statics.len() - 1)
+        } else {
+            self.dynamics.push((id, entity));
+            Slot::Dynamic(self.dynamics.len() - 1)
+        };
+        self.slots.insert(id, slot);
+        *self.counts.entry(kind).or_default() += 1;
+        self.check_entity_count();
         id
     }
 
-    pub fn create_forest(&mut self) {
+    /// live entities
+    pub fn count(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// live entities of `kind`
+    pub fn count_of_kind(&self, kind: EntityKind) -> usize {
+        self.counts.get(&kind).copied().unwrap_or_default()
+    }
+
+    /// network targets each entity buffers, deeper is smoother but further behind
+    pub fn set_interpolation_depth(&mut self, depth: usize) {
+        self.interpolation_depth = depth;
+    }
+
+    /// summed over every live entity
+    pub fn interpolation_stats(&self) -> InterpolationStats {
+        self.iter().map(|(_, e)| e.interpolation_stats()).fold(
+            InterpolationStats::default(),
+            |total, s| InterpolationStats {
+                buffered: total.buffered + s.buffered,
+                underruns: total.underruns + s.underruns,
+            },
+        )
+    }
+
+    /// logs when more than `max` entities are alive at once, None turns the check off
+    pub fn set_max_entities_warn(&mut self, max: Option<usize>) {
+        self.max_entities_warn = max;
+        self.over_max_entities = false;
+    }
+
+    fn check_entity_count(&mut self) {
+        let Some(max) = self.max_entities_warn else {
+            return;
+        };
+
+        let live = self.count();
+        if live > max && !self.over_max_entities {
+            println!(
+                "entities - {} alive ({} ever spawned), over the limit of {}",
+                live, self.entity_counter, max
+            );
+        }
+        self.over_max_entities = live > max;
+    }
+
+    /// the same world always gets the same tiles, so they never have to be sent
+    pub fn create_forest(&mut self, world: WorldInfo) {
+        let mut rng = StdRng::seed_from_u64(world.seed);
         // place tiles
         let offset = Vec2::new(1.0, -1.0);
         let scale = 1.0;
@@ -245,10 +906,8 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
         let dir = Vec2::default();
         let sprite = SpriteName::Tile;
 
-        let mut rng = thread_rng();
-
         // let rand = rand
-        let w = WORLD_SIZE;
+        let w = world.size;
         for y in (-w..=w).step_by(2) {
             for x in (-w..=w).step_by(2) {
                 let pos = Vec2::new(x as _, y as _);
@@ -268,26 +927,67 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
         // place trees
     }
 
+    /// the same world always gets the same trees, so clients can plant their own
+    pub fn plant_trees(&mut self, world: WorldInfo, sprite: SpriteName) {
+        let num_trees = 5;
+        let mut rng = StdRng::seed_from_u64(world.seed);
+
+        for _ in 0..num_trees {
+            let spread = 12.0;
+            let x = rng.gen_range((-spread)..=spread);
+            let y = rng.gen_range((-spread)..=spread);
+            self.spawn(
+                Vec2::new(x, y),
+                8.0,
+                0.0,
+                0.0,
+                Vec2::default(),
+                sprite,
+                EntityKind::Forest,
+            );
+        }
+    }
+
+    /// true if `id` refers to a live entity
+    pub fn contains(&self, id: i32) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    fn slot(&self, id: i32) -> Option<Slot> {
+        self.slots.get(&id).copied()
+    }
+
     pub fn get(&self, id: i32) -> &dyn Entity {
-        let slot = self.entities.iter().find(|(eid, _)| *eid == id).unwrap().0;
-        self.entities[slot as usize].1.as_ref()
+        let slot = self.slot(id).unwrap();
+        self.entry(slot).1.as_ref()
     }
 
     pub fn get_mut(&mut self, id: i32) -> &mut dyn Entity {
-        let slot = self.entities.iter().find(|(eid, _)| *eid == id).unwrap().0;
-        self.entities[slot as usize].1.as_mut()
+        let slot = self.slot(id).unwrap();
+        self.entry_mut(slot).1.as_mut()
     }
 
+    /// removes the entity for good, ids that are already gone are ignored
     pub fn destroy(&mut self, id: i32) {
-        // let slot = self.entities.iter().find(|(eid, _)| *eid == id).unwrap().0;
-        // self.entities.remove(slot as _);
+        let Some(slot) = self.slots.remove(&id) else {
+            return;
+        };
 
-        self.entities
-            .iter_mut()
-            .find(|e| e.0 == id)
-            .unwrap()
-            .1
-            .kill();
+        let (store, i) = match slot {
+            Slot::Static(i) => (&mut self.statics, i),
+            Slot::Dynamic(i) => (&mut self.dynamics, i),
+        };
+        let e = &store[i].1;
+        *self.counts.get_mut(&e.kind()).unwrap() -= 1;
+        for hook in self.despawn_hooks.iter_mut() {
+            hook(id, e.as_ref());
+        }
+
+        // the last entry takes the removed one's place, so its slot has to follow
+        store.swap_remove(i);
+        if let Some(&(moved, _)) = store.get(i) {
+            self.slots.insert(moved, slot);
+        }
     }
 
     pub fn spawn(
@@ -300,36 +1000,75 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
         sprite: SpriteName,
         kind: EntityKind,
     ) -> i32 {
-        let sprite = self.sprites.get(&sprite).cloned();
         let ent = BaseEntity::new(pos, scale, speed, rotation, dir, sprite, kind);
         self.emplace_entity(Box::new(ent))
     }
 
+    /// mirrors a spawn from the server, returns the local id
+    /// a duplicate or a resend whose ack got lost maps to the entity we already have, it's
+    /// been moving since, so it stays where the updates put it
+    pub fn apply_spawn(
+        &mut self,
+        server_to_local_id: &mut HashMap<i32, i32>,
+        e: EntitySpawn,
+    ) -> i32 {
+        if let Some(&lid) = server_to_local_id.get(&e.id) {
+            if self.contains(lid) {
+                return lid;
+            }
+        }
+
+        let sprite = e.kind.sprite();
+        let lid = self.spawn(e.pos, e.scale, e.speed, 0.0, e.dir, sprite, e.kind);
+        if e.kind.server_owned() {
+            // hold still until the server says otherwise
+            self.get_mut(lid).set_network_target(e.pos);
+        }
+        server_to_local_id.insert(e.id, lid);
+        lid
+    }
+
+    /// mirrors a destroy from the server, fading the entity out over `fade` first
+    /// a zero fade removes it at once, and so do statics since they never tick to finish
+    pub fn despawn(&mut self, id: i32, fade: Duration) {
+        if !self.contains(id) {
+            return;
+        }
+        let ent = self.get_mut(id);
+        if fade.is_zero() || ent.kind().is_static() {
+            self.destroy(id);
+        } else {
+            ent.fade_out(fade);
+        }
+    }
+
     // pub fn spawn_enemy(&mut self) -> usize {
     //     // network this
     //     unimplemented!()
     // }
 
-    pub fn spawn_player<'a: 'e>(
+    pub fn spawn_player(
         &mut self,
         rx: Receiver<KeyEvent>,
         ptx: Sender<Vec2>,
         sock: &socket::Client,
     ) -> i32 {
-        let sprite = self.sprites[&SpriteName::Deer].clone();
-        let pos = Vec2::new(1.0, 2.0);
         let scale = 4.0;
+        // the server moves it if it landed in something
+        let pos = Vec2::new(1.0, 2.0);
         let speed = 12.0;
         let dir = Vec2::default();
-        let base = BaseEntity::new(
+        let mut base = BaseEntity::new(
             pos,
             scale,
             speed,
             0.0,
             dir,
-            Some(sprite),
+            SpriteName::Deer,
             EntityKind::Player,
         );
+        // our own deer follows input, not the network
+        base.interpolated = false;
         let ent = Player::new(base, rx, ptx);
 
         let packet = EntitySpawn {
@@ -342,7 +1081,14 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
         };
         sock.send(packet).unwrap();
 
-        self.emplace_entity(Box::new(ent))
+        let id = self.emplace_entity(Box::new(ent));
+        self.get_mut(id).set_owner(Some(id));
+        id
+    }
+
+    /// stops simulating everything `owner` doesn't own, it only follows network updates
+    pub fn set_local_owner(&mut self, owner: Option<i32>) {
+        self.local_owner = owner;
     }
 
     // pub fn spawn_projectile(&mut self) -> usize {
@@ -351,8 +1097,14 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
     // }
 
     pub fn set_position(&mut self, id: i32, pos: Vec2) {
-        if let Some((_, e)) = self.entities.iter_mut().find(|(eid, _)| *eid == id) {
-            e.set_pos(pos);
+        if let Some(slot) = self.slot(id) {
+            self.entry_mut(slot).1.set_pos(pos);
+        }
+    }
+
+    pub fn set_rotation(&mut self, id: i32, rotation: f32) {
+        if let Some(slot) = self.slot(id) {
+            self.entry_mut(slot).1.set_rotation(rotation);
         }
     }
 
@@ -371,19 +1123,681 @@ impl<'e, 's: 'e> EntityManager<'e, 's> {
         //     // fix z ordering so removing entities will work properly
         // }
 
-        // tick all alive entities
-        self.entities
-            .iter_mut()
-            .filter(|e| e.1.is_alive())
-            .for_each(|e| {
-                e.1.tick(dt);
-            });
+        // tick every entity, each one only touches itself so they can run in parallel
+        // anything that looks at other entities (collision, steering) happens in its own pass
+        let local_owner = self.local_owner;
+        self.dynamics.par_iter_mut().for_each(|e| {
+            if local_owner.is_some() {
+                e.1.set_simulated(e.1.owner() == local_owner);
+            }
+            e.1.tick(dt);
+        });
+
+        self.retain(|_, e| !e.expired());
+    }
+
+    /// destroys everything `owner` left behind, like its spit, and returns what went
+    pub fn destroy_by_owner(&mut self, owner: i32) -> Vec<i32> {
+        let owned = self
+            .iter()
+            .filter(|(_, e)| e.owner() == Some(owner))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for &id in owned.iter() {
+            self.destroy(id);
+        }
+        owned
     }
 
-    pub fn render(&self, shader: &Shader) {
-        self.entities
+    /// destroys every entity `f` returns false for, firing the despawn hooks
+    pub fn retain(&mut self, mut f: impl FnMut(i32, &dyn Entity) -> bool) {
+        let removals = self
             .iter()
-            .filter(|e| e.1.is_alive())
-            .for_each(|(_, e)| e.render(shader));
+            .filter(|&(id, e)| !f(id, e))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for id in removals {
+            self.destroy(id);
+        }
+    }
+
+    /// freezes networked entities that haven't heard from the server in `stale_after` seconds
+    pub fn mark_stale(&mut self, stale_after: f32) {
+        for (_, e) in self.dynamics.iter_mut() {
+            if e.since_update().is_some_and(|t| t > stale_after) {
+                e.set_stale(true);
+            }
+        }
+    }
+
+    pub fn render(&self, shader: &Shader, view: Mat3) {
+        self.render_with(&mut self.sprite_renderer(shader), view);
+    }
+
+    /// for drawing the loaded sprites outside of any entity, like hud markers
+    pub fn sprite_renderer<'r>(&'r self, shader: &'r Shader<'s>) -> SpriteRenderer<'r, 's> {
+        SpriteRenderer::new(shader, &self.sprites)
+    }
+
+    /// draws every live entity through `renderer` instead of the engine
+    /// lower layers first, within a layer in spawn order so overlapping sprites don't swap
+    /// places from one frame to the next
+    pub fn render_with(&self, renderer: &mut dyn Renderer, view: Mat3) {
+        let mut drawn = self.iter().collect::<Vec<_>>();
+        // stable, so spawn order survives as the tie-break
+        drawn.sort_by_key(|(_, e)| e.kind().layer());
+        drawn.iter().for_each(|(_, e)| e.render(renderer, view));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::RecordingRenderer;
+
+    fn spawn_at(ents: &mut EntityManager, kind: EntityKind, pos: Vec2) -> i32 {
+        ents.spawn(pos, 1.0, 0.0, 0.0, Vec2::default(), SpriteName::None, kind)
+    }
+
+    #[test]
+    fn churning_past_the_warn_limit_only_warns_about_live_entities() {
+        let mut ents = EntityManager::default();
+        ents.set_max_entities_warn(Some(4));
+        for _ in 0..1000 {
+            let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+            ents.destroy(id);
+        }
+        assert!(!ents.over_max_entities);
+
+        for _ in 0..5 {
+            spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        }
+        assert!(ents.over_max_entities);
+    }
+
+    #[test]
+    fn destroy_frees_the_entity_and_keeps_the_others_reachable() {
+        let mut ents = EntityManager::default();
+        let ids = (0..4)
+            .map(|i| spawn_at(&mut ents, EntityKind::Enemy, Vec2::new(i as f32, 0.0)))
+            .collect::<Vec<_>>();
+        ents.destroy(ids[0]);
+        ents.destroy(ids[2]);
+
+        assert_eq!(ents.dynamics.len(), 2);
+        assert!(!ents.contains(ids[0]));
+        assert_eq!(ents.get(ids[3]).pos().x, 3.0);
+        assert_eq!(ents.get(ids[1]).pos().x, 1.0);
+        let left = ents.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(left, [ids[1], ids[3]]);
+    }
+
+    #[test]
+    fn destroying_twice_only_fires_the_hook_once() {
+        let fired = std::cell::Cell::new(0);
+        let mut ents = EntityManager::default();
+        ents.on_despawn(|_, _| fired.set(fired.get() + 1));
+        let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        ents.destroy(id);
+        ents.destroy(id);
+        ents.destroy(1234);
+        assert_eq!(fired.get(), 1);
+        assert_eq!(ents.count(), 0);
+    }
+
+    #[test]
+    fn retain_removes_from_the_stores() {
+        let mut ents = EntityManager::default();
+        spawn_at(&mut ents, EntityKind::Tile, Vec2::default());
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        spawn_at(&mut ents, EntityKind::PlayerProjectile, Vec2::default());
+        ents.retain(|_, e| e.kind() != EntityKind::PlayerProjectile);
+
+        assert_eq!(ents.statics.len(), 1);
+        assert_eq!(ents.dynamics.len(), 1);
+        assert_eq!(
+            ents.iter_mut_kind(EntityKind::Enemy).next().unwrap().0,
+            hunter
+        );
+    }
+
+    fn record(ents: &EntityManager) -> Vec<(SpriteName, Transform)> {
+        let mut recorder = RecordingRenderer::default();
+        ents.render_with(&mut recorder, Mat3::scale(Vec2::new(1.0, 1.0)));
+        recorder.calls
+    }
+
+    #[test]
+    fn forest_floor_is_a_grid_of_quarter_turned_tiles() {
+        let world = WorldInfo {
+            seed: 7,
+            size: 4,
+            local_statics: true,
+        };
+        let mut ents = EntityManager::default();
+        ents.create_forest(world);
+        let calls = record(&ents);
+
+        // every other cell from -4 to 4, nudged by (1, -1), row by row from the bottom
+        let expected = [-4.0, -2.0, 0.0, 2.0, 4.0]
+            .into_iter()
+            .flat_map(|y| [-4.0, -2.0, 0.0, 2.0, 4.0].map(|x| (x + 1.0, y - 1.0)))
+            .collect::<Vec<_>>();
+        assert_eq!(calls.len(), expected.len());
+        for ((sprite, t), (x, y)) in calls.iter().zip(expected) {
+            assert_eq!(*sprite, SpriteName::Tile);
+            assert_eq!((t.pos.x, t.pos.y), (x, y));
+            assert_eq!((t.scale.x, t.scale.y), (1.0, 1.0));
+            assert!([0.0, 90.0, 180.0, 270.0, 360.0].contains(&t.rotation));
+        }
+
+        // the same seed lays the same floor
+        let mut again = EntityManager::default();
+        again.create_forest(world);
+        let rotations = |calls: &[(SpriteName, Transform)]| {
+            calls.iter().map(|c| c.1.rotation).collect::<Vec<_>>()
+        };
+        assert_eq!(rotations(&record(&again)), rotations(&calls));
+    }
+
+    #[test]
+    fn topmost_at_picks_the_highest_layer_under_the_point() {
+        let mut ents = EntityManager::default();
+        spawn_at(&mut ents, EntityKind::Tile, Vec2::default());
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        let tree = spawn_at(&mut ents, EntityKind::Forest, Vec2::new(1.5, 0.0));
+
+        assert_eq!(ents.topmost_at(Vec2::new(-0.5, 0.0)), Some(hunter));
+        assert_eq!(ents.topmost_at(Vec2::new(0.75, 0.0)), Some(tree));
+        assert_eq!(ents.topmost_at(Vec2::new(10.0, 10.0)), None);
+
+        // a tie goes to the one drawn last
+        let later = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        assert_eq!(ents.topmost_at(Vec2::new(-0.5, 0.0)), Some(later));
+    }
+
+    #[test]
+    fn entities_go_when_their_ttl_runs_out() {
+        let mut ents = EntityManager::default();
+        let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        ents.get_mut(id).set_ttl(Duration::from_millis(100));
+
+        ents.tick(0.05);
+        assert!(ents.contains(id));
+        ents.tick(0.1);
+        assert!(!ents.contains(id));
+    }
+
+    #[test]
+    fn effects_wear_off_and_are_reported_once() {
+        let mut ents = EntityManager::default();
+        let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        ents.get_mut(id)
+            .add_effect(Effect::Stun, Duration::from_millis(100));
+
+        ents.tick(0.05);
+        assert!(ents.get(id).has_effect(Effect::Stun));
+        // re-applying restarts the clock
+        ents.get_mut(id)
+            .add_effect(Effect::Stun, Duration::from_millis(100));
+        ents.tick(0.07);
+        assert!(ents.get(id).has_effect(Effect::Stun));
+        assert!(ents.get_mut(id).take_expired_effects().is_empty());
+
+        ents.tick(0.05);
+        assert!(!ents.get(id).has_effect(Effect::Stun));
+        assert_eq!(ents.get_mut(id).take_expired_effects(), [Effect::Stun]);
+        assert!(ents.get_mut(id).take_expired_effects().is_empty());
+
+        // removed early, it doesn't count as wearing off
+        ents.get_mut(id)
+            .add_effect(Effect::DamageFlash, Duration::from_millis(100));
+        ents.get_mut(id).remove_effect(Effect::DamageFlash);
+        ents.tick(0.2);
+        assert!(ents.get_mut(id).take_expired_effects().is_empty());
+    }
+
+    #[test]
+    fn iter_mut_kind_only_touches_that_kind() {
+        let mut ents = EntityManager::default();
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        let deer = spawn_at(&mut ents, EntityKind::Player, Vec2::default());
+        let tree = spawn_at(&mut ents, EntityKind::Forest, Vec2::default());
+        let other_tree = spawn_at(&mut ents, EntityKind::Forest, Vec2::default());
+
+        ents.iter_mut_kind(EntityKind::Forest)
+            .for_each(|(_, e)| e.set_rotation(90.0));
+        ents.iter_mut_kind(EntityKind::Enemy)
+            .for_each(|(_, e)| e.set_rotation(45.0));
+
+        assert_eq!(ents.get(tree).rotation(), 90.0);
+        assert_eq!(ents.get(other_tree).rotation(), 90.0);
+        assert_eq!(ents.get(hunter).rotation(), 45.0);
+        assert_eq!(ents.get(deer).rotation(), 0.0);
+    }
+
+    #[test]
+    fn nearest_of_kind_breaks_ties_by_id() {
+        let mut ents = EntityManager::default();
+        let far = spawn_at(&mut ents, EntityKind::Enemy, Vec2::new(5.0, 0.0));
+        let left = spawn_at(&mut ents, EntityKind::Enemy, Vec2::new(-1.0, 0.0));
+        let right = spawn_at(&mut ents, EntityKind::Enemy, Vec2::new(1.0, 0.0));
+        spawn_at(&mut ents, EntityKind::Player, Vec2::default());
+
+        let ids = |found: Vec<(i32, Vec2)>| found.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(ents.nearest_of_kind(Vec2::default(), EntityKind::Enemy, 2)),
+            [left, right]
+        );
+        assert_eq!(
+            ids(ents.nearest_of_kind(Vec2::default(), EntityKind::Enemy, 9)),
+            [left, right, far]
+        );
+
+        // a respawn at the same spot gets a newer id, so it loses the tie
+        ents.destroy(left);
+        let back = spawn_at(&mut ents, EntityKind::Enemy, Vec2::new(-1.0, 0.0));
+        assert_eq!(
+            ids(ents.nearest_of_kind(Vec2::default(), EntityKind::Enemy, 2)),
+            [right, back]
+        );
+    }
+
+    #[test]
+    fn counts_follow_spawns_and_destroys() {
+        let mut ents = EntityManager::default();
+        let hunters = (0..3)
+            .map(|_| spawn_at(&mut ents, EntityKind::Enemy, Vec2::default()))
+            .collect::<Vec<_>>();
+        spawn_at(&mut ents, EntityKind::Forest, Vec2::default());
+        assert_eq!(ents.count(), 4);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 3);
+        assert_eq!(ents.count_of_kind(EntityKind::Forest), 1);
+        assert_eq!(ents.count_of_kind(EntityKind::Player), 0);
+
+        ents.destroy(hunters[1]);
+        ents.destroy(hunters[1]);
+        ents.retain(|_, e| e.kind() != EntityKind::Forest);
+        assert_eq!(ents.count(), 2);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 2);
+        assert_eq!(ents.count_of_kind(EntityKind::Forest), 0);
+    }
+
+    #[test]
+    fn layers_draw_bottom_up_and_keep_spawn_order_within_a_layer() {
+        let mut ents = EntityManager::default();
+        let spawned = [
+            (EntityKind::Enemy, SpriteName::Hunter, 0.0),
+            (EntityKind::Tile, SpriteName::Tile, 1.0),
+            (EntityKind::PlayerProjectile, SpriteName::Spit, 2.0),
+            (EntityKind::Forest, SpriteName::Forest, 3.0),
+            (EntityKind::Tile, SpriteName::Tile, 4.0),
+            (EntityKind::Player, SpriteName::Deer, 5.0),
+        ];
+        for (kind, sprite, x) in spawned {
+            ents.spawn(
+                Vec2::new(x, 0.0),
+                1.0,
+                0.0,
+                0.0,
+                Vec2::default(),
+                sprite,
+                kind,
+            );
+        }
+
+        let drawn = record(&ents)
+            .into_iter()
+            .map(|(sprite, t)| (sprite, t.pos.x))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            drawn,
+            [
+                (SpriteName::Tile, 1.0),
+                (SpriteName::Tile, 4.0),
+                (SpriteName::Spit, 2.0),
+                (SpriteName::Hunter, 0.0),
+                (SpriteName::Deer, 5.0),
+                (SpriteName::Forest, 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn spit_is_smaller_than_its_sprite() {
+        let mut ents = EntityManager::default();
+        let spit = ents.spawn(
+            Vec2::default(),
+            2.0,
+            0.0,
+            0.0,
+            Vec2::default(),
+            SpriteName::Spit,
+            EntityKind::PlayerProjectile,
+        );
+        let hunter = ents.spawn(
+            Vec2::default(),
+            2.0,
+            0.0,
+            0.0,
+            Vec2::default(),
+            SpriteName::Hunter,
+            EntityKind::Enemy,
+        );
+
+        assert_eq!(ents.get(spit).radius(), 1.0);
+        assert_eq!(ents.get(hunter).radius(), 2.0);
+    }
+
+    #[test]
+    fn prev_pos_is_where_the_last_tick_started() {
+        let mut ents = EntityManager::default();
+        let start = Vec2::new(1.0, 2.0);
+        let id = ents.spawn(
+            start,
+            1.0,
+            10.0,
+            0.0,
+            Vec2::new(1.0, 0.0),
+            SpriteName::None,
+            EntityKind::Enemy,
+        );
+        assert_eq!(ents.get(id).prev_pos().x, start.x);
+
+        ents.tick(0.5);
+        let e = ents.get(id);
+        assert_eq!((e.prev_pos().x, e.prev_pos().y), (start.x, start.y));
+        assert_eq!((e.pos().x, e.pos().y), (6.0, 2.0));
+
+        ents.tick(0.5);
+        assert_eq!(ents.get(id).prev_pos().x, 6.0);
+    }
+
+    #[test]
+    fn uninterpolated_entities_take_network_positions_at_once() {
+        let target = Vec2::new(5.0, 0.0);
+        let deer = |interpolated| {
+            let zero = Vec2::default();
+            let mut e = BaseEntity::new(
+                zero,
+                1.0,
+                0.0,
+                0.0,
+                zero,
+                SpriteName::None,
+                EntityKind::Player,
+            );
+            e.interpolated = interpolated;
+            e.set_network_target(target);
+            e
+        };
+
+        assert_eq!(deer(false).pos().x, target.x);
+
+        let mut remote = deer(true);
+        assert_eq!(remote.pos().x, 0.0);
+        remote.tick(0.01);
+        assert!(0.0 < remote.pos().x && remote.pos().x < target.x);
+    }
+
+    #[test]
+    fn entities_freeze_once_updates_stop_and_resume_with_the_next_one() {
+        let mut ents = EntityManager::default();
+        let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        ents.get_mut(id).set_simulated(false);
+        ents.get_mut(id).set_network_target(Vec2::new(1000.0, 0.0));
+
+        let stale_after = 0.05;
+        let step = |ents: &mut EntityManager| {
+            let before = ents.get(id).pos().x;
+            ents.tick(0.01);
+            ents.mark_stale(stale_after);
+            ents.get(id).pos().x - before
+        };
+        for _ in 0..5 {
+            assert!(step(&mut ents) > 0.0);
+            assert!(!ents.get(id).is_stale());
+        }
+        step(&mut ents);
+        assert!(ents.get(id).is_stale());
+        // nowhere near the target, but it stays put
+        assert_eq!(step(&mut ents), 0.0);
+        assert_eq!(step(&mut ents), 0.0);
+
+        ents.get_mut(id).set_network_target(Vec2::new(1000.0, 0.0));
+        assert!(!ents.get(id).is_stale());
+        assert!(step(&mut ents) > 0.0);
+    }
+
+    #[test]
+    fn the_buffer_keeps_at_most_the_configured_depth() {
+        let mut ents = EntityManager::default();
+        ents.set_interpolation_depth(3);
+        let id = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        for x in 1..=5 {
+            ents.get_mut(id)
+                .set_network_target(Vec2::new(x as f32, 0.0));
+        }
+        assert_eq!(ents.interpolation_stats().buffered, 3);
+
+        // the oldest were dropped, so the newest is where it ends up
+        for _ in 0..100 {
+            ents.tick(0.1);
+        }
+        let stats = ents.interpolation_stats();
+        assert_eq!(ents.get(id).pos().x, 5.0);
+        assert_eq!(stats.buffered, 1);
+        // running dry is counted once, not every tick it stays dry
+        assert_eq!(stats.underruns, 1);
+    }
+
+    #[test]
+    fn stale_entities_are_drawn_smaller_until_they_hear_back() {
+        let mut ents = EntityManager::default();
+        let pos = Vec2::default();
+        let dir = Vec2::default();
+        let kind = EntityKind::Enemy;
+        let id = ents.spawn(pos, 2.0, 0.0, 0.0, dir, SpriteName::Hunter, kind);
+        let drawn_scale = |ents: &EntityManager| record(ents)[0].1.scale.y;
+        assert_eq!(drawn_scale(&ents), 2.0);
+
+        ents.get_mut(id).set_stale(true);
+        assert_eq!(drawn_scale(&ents), 2.0 * STALE_SCALE);
+
+        ents.get_mut(id).set_network_target(Vec2::new(1.0, 0.0));
+        assert_eq!(drawn_scale(&ents), 2.0);
+    }
+
+    #[test]
+    fn the_same_spawn_twice_makes_one_entity() {
+        let mut ents = EntityManager::default();
+        let mut server_to_local_id = HashMap::new();
+        let spawn = EntitySpawn {
+            id: 42,
+            kind: EntityKind::Enemy,
+            pos: Vec2::new(3.0, 4.0),
+            scale: 1.0,
+            speed: 2.0,
+            dir: Vec2::default(),
+        };
+        let lid = ents.apply_spawn(&mut server_to_local_id, spawn);
+        ents.set_position(lid, Vec2::new(5.0, 5.0));
+
+        // a resend carries the old position, the entity stays where it was moved
+        assert_eq!(ents.apply_spawn(&mut server_to_local_id, spawn), lid);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 1);
+        assert_eq!(ents.get(lid).pos().x, 5.0);
+
+        // once it's gone locally the next spawn brings it back
+        ents.destroy(lid);
+        let again = ents.apply_spawn(&mut server_to_local_id, spawn);
+        assert!(ents.contains(again));
+        assert_eq!(server_to_local_id[&42], again);
+    }
+
+    #[test]
+    fn the_dump_has_a_line_for_every_live_entity() {
+        let mut ents = EntityManager::default();
+        let zero = Vec2::default();
+        let kinds = [EntityKind::Tile, EntityKind::Player, EntityKind::Enemy];
+        let ids = kinds
+            .map(|kind| ents.spawn(zero, 1.0, 0.0, 0.0, zero, SpriteName::None, kind))
+            .to_vec();
+        let gone = ents.spawn(
+            zero,
+            1.0,
+            0.0,
+            0.0,
+            zero,
+            SpriteName::None,
+            EntityKind::Enemy,
+        );
+        ents.destroy(gone);
+        // a NaN would make the whole line unparseable
+        ents.set_position(ids[2], Vec2::new(f32::NAN, 1.0));
+        #[cfg(debug_assertions)]
+        ents.get_mut(ids[1])
+            .set_label("deer of \"bob\"\n".to_owned());
+
+        let dump = ents.debug_dump();
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), ids.len());
+        for id in ids {
+            let prefix = format!("{{\"id\":{},", id);
+            assert_eq!(lines.iter().filter(|l| l.starts_with(&prefix)).count(), 1);
+        }
+        assert!(!dump.contains(&format!("\"id\":{},", gone)));
+        assert!(dump.contains("\"pos\":[null,1.000]"));
+        assert!(!dump.contains("NaN"));
+        #[cfg(debug_assertions)]
+        assert!(dump.contains(r#""label":"deer of \"bob\"\u000a""#));
+    }
+
+    #[test]
+    fn with_a_local_owner_only_its_entities_move_without_updates() {
+        let mut ents = EntityManager::default();
+        let moving = |ents: &mut EntityManager, kind| {
+            ents.spawn(
+                Vec2::default(),
+                1.0,
+                10.0,
+                0.0,
+                Vec2::new(1.0, 0.0),
+                SpriteName::None,
+                kind,
+            )
+        };
+        let deer = moving(&mut ents, EntityKind::Player);
+        ents.get_mut(deer).set_owner(Some(deer));
+        let hunter = moving(&mut ents, EntityKind::Enemy);
+        ents.set_local_owner(Some(deer));
+
+        ents.tick(0.5);
+        assert_eq!(ents.get(deer).pos().x, 5.0);
+        assert_eq!(ents.get(hunter).pos().x, 0.0);
+
+        // network updates still move it
+        ents.get_mut(hunter)
+            .set_network_target(Vec2::new(-3.0, 0.0));
+        ents.tick(0.5);
+        assert!(ents.get(hunter).pos().x < 0.0);
+    }
+
+    #[test]
+    fn a_stunned_entity_stays_put_until_the_stun_wears_off() {
+        let mut ents = EntityManager::default();
+        let hunter = ents.spawn(
+            Vec2::default(),
+            1.0,
+            10.0,
+            0.0,
+            Vec2::new(1.0, 0.0),
+            SpriteName::None,
+            EntityKind::Enemy,
+        );
+        ents.get_mut(hunter)
+            .add_effect(Effect::Stun, Duration::from_millis(100));
+
+        for _ in 0..3 {
+            ents.tick(0.03);
+            assert_eq!(ents.get(hunter).pos().x, 0.0);
+        }
+        ents.tick(0.03);
+        assert!(!ents.get(hunter).has_effect(Effect::Stun));
+        assert!(ents.get(hunter).pos().x > 0.0);
+    }
+
+    #[test]
+    fn unlabelled_entities_are_named_by_kind_and_id() {
+        let mut ents = EntityManager::default();
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        let deer = spawn_at(&mut ents, EntityKind::Player, Vec2::default());
+        assert_eq!(ents.label(hunter), format!("Enemy#{}", hunter));
+        assert_eq!(ents.label(deer), format!("Player#{}", deer));
+
+        #[cfg(debug_assertions)]
+        {
+            ents.get_mut(deer).set_label("bob".to_owned());
+            assert_eq!(ents.label(deer), "bob");
+            assert_eq!(ents.label(hunter), format!("Enemy#{}", hunter));
+        }
+    }
+
+    #[test]
+    fn destroy_by_owner_only_takes_that_owners_entities() {
+        let destroyed = std::cell::RefCell::new(Vec::new());
+        let mut ents = EntityManager::default();
+        ents.on_despawn(|id, _| destroyed.borrow_mut().push(id));
+        let zero = Vec2::default();
+        let deer = spawn_at(&mut ents, EntityKind::Player, zero);
+        let other = spawn_at(&mut ents, EntityKind::Player, zero);
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, zero);
+        let spit = |ents: &mut EntityManager, owner| {
+            let id = spawn_at(ents, EntityKind::PlayerProjectile, zero);
+            ents.get_mut(id).set_owner(Some(owner));
+            id
+        };
+        let mine = vec![spit(&mut ents, deer), spit(&mut ents, deer)];
+        let theirs = spit(&mut ents, other);
+
+        assert_eq!(ents.destroy_by_owner(deer), mine);
+        assert_eq!(*destroyed.borrow(), mine);
+        for id in [deer, other, hunter, theirs] {
+            assert!(ents.contains(id));
+        }
+        assert!(ents.destroy_by_owner(deer).is_empty());
+    }
+
+    #[test]
+    fn a_despawned_entity_fades_for_the_fade_duration_then_goes() {
+        let mut ents = EntityManager::default();
+        let hunter = ents.spawn(
+            Vec2::default(),
+            2.0,
+            10.0,
+            0.0,
+            Vec2::new(1.0, 0.0),
+            SpriteName::Hunter,
+            EntityKind::Enemy,
+        );
+        ents.despawn(hunter, Duration::from_millis(200));
+
+        ents.tick(0.1);
+        assert!(ents.contains(hunter));
+        // it stops where it was and shrinks away
+        assert_eq!(ents.get(hunter).pos().x, 0.0);
+        let (_, t) = record(&ents)[0];
+        assert!(0.0 < t.scale.y && t.scale.y < 2.0);
+
+        ents.tick(0.11);
+        assert!(!ents.contains(hunter));
+
+        // no fade, and statics that never tick, go at once
+        let hunter = spawn_at(&mut ents, EntityKind::Enemy, Vec2::default());
+        ents.despawn(hunter, Duration::ZERO);
+        assert!(!ents.contains(hunter));
+        let tree = spawn_at(&mut ents, EntityKind::Forest, Vec2::default());
+        ents.despawn(tree, Duration::from_millis(200));
+        assert!(!ents.contains(tree));
     }
 }
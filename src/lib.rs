@@ -0,0 +1,13 @@
+//! everything but the window, built as a library so the binary and the benches share one copy
+
+pub mod camera;
+pub mod common;
+pub mod config;
+pub mod console;
+pub mod entities;
+pub mod map;
+pub mod math_ext;
+pub mod renderer;
+pub mod server;
+pub mod socket;
+pub mod timing;
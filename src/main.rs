@@ -3,24 +3,58 @@
 use std::collections::HashMap;
 use std::env;
 use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::path::Path;
-use std::str::FromStr;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-use common::EntityDestroy;
-use common::EntityKind;
-use common::SpriteName;
-use common::TIMEOUT;
-use engine_2d::render;
-
-extern crate engine_2d;
+use deer_defense::camera::Camera;
+use deer_defense::common;
+use deer_defense::common::shot_directions;
+use deer_defense::common::Collision;
+use deer_defense::common::EntityDestroy;
+use deer_defense::common::EntityKind;
+use deer_defense::common::EntitySpawn;
+use deer_defense::common::EntityUpdate;
+use deer_defense::common::Fire;
+use deer_defense::common::FireAck;
+use deer_defense::common::GameOver;
+use deer_defense::common::GameOverReason;
+use deer_defense::common::Identify;
+use deer_defense::common::OpCode;
+use deer_defense::common::Powerup;
+use deer_defense::common::PowerupApplied;
+use deer_defense::common::SpawnAck;
+use deer_defense::common::SpriteName;
+use deer_defense::common::Stun;
+use deer_defense::common::WorldInfo;
+use deer_defense::common::RAPID_FIRE_RATE;
+use deer_defense::common::TIMEOUT;
+use deer_defense::config;
+use deer_defense::config::GameConfig;
+use deer_defense::console;
+use deer_defense::console::ClientCommand;
+use deer_defense::entities::Effect;
+use deer_defense::entities::EntityManager;
+use deer_defense::entities::KeyEvent;
+use deer_defense::renderer::Renderer;
+use deer_defense::renderer::Transform;
+use deer_defense::server;
+use deer_defense::socket;
+use deer_defense::socket::Client;
+use deer_defense::socket::Packet;
+use deer_defense::timing::ClockSync;
+use deer_defense::timing::FrameTimer;
+use deer_defense::timing::Interval;
+use engine_2d::math::Mat3;
 use engine_2d::math::Vec2;
+use engine_2d::render;
 use engine_2d::render::shader::IShaderBuilder;
 use engine_2d::render::shader::PartType;
 use engine_2d::render::shader::Shader;
@@ -33,19 +67,8 @@ use engine_2d::window::DrawContext;
 use engine_2d::window::Engine;
 use engine_2d::window::GameLoop;
 use engine_2d::window::WindowManager;
-use entities::EntityManager;
-use entities::KeyEvent;
-use socket::Client;
-use socket::Packet;
-
-use crate::common::EntitySpawn;
-use crate::common::EntityUpdate;
-use crate::common::OpCode;
-
-mod common;
-mod entities;
-mod server;
-mod socket;
+use rand::thread_rng;
+use rand::Rng;
 
 fn make_shader<'c>(ctx: &'c DrawContext) -> Shader<'c> {
     ShaderBuilder::new(ctx)
@@ -89,124 +112,503 @@ fn make_shader<'c>(ctx: &'c DrawContext) -> Shader<'c> {
         .unwrap()
 }
 
+/// returning drops `tx`, which is how the game finds out the connection is gone
+/// a Bye is passed on first so a kick isn't mistaken for a dropped connection
 fn recv_loop(socket: Arc<Client>, tx: Sender<Packet>) {
     loop {
-        if let Ok(msg) = socket.recv() {
-            tx.send(msg).unwrap();
-        } else {
-            break;
+        match socket.recv() {
+            Ok(msg) => {
+                if tx.send(msg).is_err() {
+                    // the game already moved on
+                    break;
+                }
+            }
+            Err(socket::Error::Disconnected) => {
+                let _ = tx.send(Packet::new(socket::OpCode::Bye, socket::NoData));
+                break;
+            }
+            Err(socket::Error::PacketTooLarge) => println!("client - dropped an oversized packet"),
+            Err(e) => {
+                println!("client - recv_loop stopped: {}", e);
+                break;
+            }
         }
     }
 }
 
+/// how many of the closest hunters get an arrow when they're off screen
+const THREAT_MARKERS: usize = 3;
+
+/// each zoom command scales the zoom by this much
+const ZOOM_STEP: f32 = 1.25;
+/// how quickly the camera eases to the chosen zoom
+const ZOOM_RATE: f32 = 8.0;
+
+/// longest wait between two connect attempts
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(4);
+
+/// binds a fresh socket and says hello to the server, backing off between attempts
+fn connect(address: SocketAddr, retries: u32, tx: Sender<socket::Result<(Client, WorldInfo)>>) {
+    let mut backoff = Duration::from_millis(250);
+    let mut attempt = 0;
+    let result = loop {
+        let result = Client::new().and_then(|sock| {
+            let hello = sock.connect(address)?;
+            Ok((sock, WorldInfo::from_bytes(&hello)?))
+        });
+        attempt += 1;
+        match result {
+            Err(e) if attempt <= retries => {
+                println!(
+                    "client - connect attempt {} failed ({}), retrying in {:?}",
+                    attempt, e, backoff
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+            }
+            result => break result,
+        }
+    };
+
+    // the game may have stopped waiting for us
+    let _ = tx.send(result);
+}
+
+/// a splat of spit that blinks out where a hit landed
+fn spawn_hit_effect(ents: &mut EntityManager, pos: Vec2) {
+    let life = Duration::from_millis(150);
+    let lid = ents.spawn(
+        pos,
+        8.0,
+        0.0,
+        0.0,
+        Vec2::default(),
+        SpriteName::Spit,
+        EntityKind::Effect,
+    );
+    let splat = ents.get_mut(lid);
+    splat.set_ttl(life);
+    splat.add_effect(Effect::DamageFlash, life);
+}
+
+/// how the deer's powerups change its shooting
+struct Modifiers {
+    fire_rate: u32,
+    spread: bool,
+}
+
+pub enum GameState {
+    /// waiting for the player to pick a server
+    Menu,
+    Connecting(Receiver<socket::Result<(Client, WorldInfo)>>),
+    Playing(Connection),
+    /// still connected, waiting for the server to start the next round
+    GameOver(Connection, GameOverReason),
+    Disconnected(String),
+}
+
+/// everything that only exists while connected to a server
+pub struct Connection {
+    sock: Arc<Client>,
+    rx_packet: Receiver<Packet>,
+    player_id: i32,
+    prx: Receiver<Vec2>,
+    ktx: Sender<KeyEvent>,
+}
+
 // 'a: 'b means a outlives 'b
 // g is the lifetime of gl objects
 // c is the lifetime of the gl context
 // w is the lifetime of the window
 pub struct Game<'e, 's: 'e> {
+    config: GameConfig,
+    frame_timer: FrameTimer,
+    show_fps: bool,
+    fps_timer: Timer,
+    position_log: Option<Interval>,
+    position_seq: u32,
+    state: GameState,
+    /// typed into stdin
+    commands: Receiver<ClientCommand>,
+    address: SocketAddr,
+    /// lets the server hand our deer back after a reconnect
+    token: u64,
+
     ping_timer: Timer,
+    /// when the last unanswered ping went out
+    ping_sent: Option<Instant>,
+    clock: ClockSync,
     player_pos_timer: Timer,
     timeout_timer: Timer,
     shot_cooldown: Cooldown,
-
-    prx: Receiver<Vec2>,
-    ktx: Sender<(bool, bool, bool, bool)>,
+    /// counts up with every predicted spit
+    next_correlation: u16,
+    /// local ids of spit we spawned ahead of the server, by correlation id
+    predicted_shots: HashMap<u16, i32>,
+    /// the tiles on screen were laid out for this world
+    world: Option<WorldInfo>,
+    /// the server hasn't started this round's wave yet
+    waiting_for_wave: bool,
+    /// voted to start the wave, resent with every ping until it starts
+    ready: bool,
 
     shader: Shader<'s>,
     ents: EntityManager<'e, 's>,
+    camera: Camera,
+    /// the zoom the camera is easing towards
+    zoom: f32,
+    #[cfg(debug_assertions)]
+    god_view: bool,
 
-    sock: Arc<socket::Client>,
-    rx_packet: Receiver<Packet>,
     server_to_local_id: HashMap<i32, i32>,
-    player_id: i32,
 }
 
 impl<'e, 's: 'e, 'c: 's> GameLoop<'c> for Game<'e, 's> {
     fn setup(ctx: &'c DrawContext, wm: &mut WindowManager) -> Self {
-        let sock = Arc::new(socket::Client::new().unwrap());
-        sock.connect((Ipv4Addr::LOCALHOST, 7777)).unwrap();
-
-        let sock_ = sock.clone();
-        let (tx, rx) = mpsc::channel();
-        let (ptx, prx) = mpsc::channel();
-        let (ktx, krx) = mpsc::channel();
-        thread::spawn(move || recv_loop(sock_, tx));
-
+        let config = config::get();
+        config.check_ping_interval(TIMEOUT);
         let mut ents = EntityManager::default();
+        ents.set_max_entities_warn(config.max_entities_warn);
+        ents.set_interpolation_depth(config.interpolation_depth);
         ents.load_sprite(ctx, SpriteName::Tile, Path::new("tile.png"));
         ents.load_sprite(ctx, SpriteName::Deer, Path::new("deer.png"));
         ents.load_sprite(ctx, SpriteName::Forest, Path::new("pine.png"));
         ents.load_sprite(ctx, SpriteName::Spit, Path::new("spit.png"));
         ents.load_sprite(ctx, SpriteName::Hunter, Path::new("hunter.png"));
-        ents.create_forest();
-        let player_id = ents.spawn_player(krx, ptx, &sock);
+
+        let (command_tx, commands) = mpsc::channel();
+        thread::spawn(move || console::console_loop(command_tx));
 
         let shader = make_shader(&ctx);
-        Self {
-            ktx,
-            prx,
+        let mut game = Self {
+            config,
+            frame_timer: FrameTimer::new(config.max_dt, config.dt_smoothing),
+            show_fps: config.show_fps,
+            fps_timer: Timer::new(Duration::from_secs(1)),
+            position_log: config.position_log_interval.map(Interval::new),
+            position_seq: 0,
+            state: GameState::Menu,
+            commands,
+            address: config.server_address,
+            token: thread_rng().gen(),
             shader,
             ents,
-            sock,
+            camera: Camera::default(),
+            zoom: Camera::default().zoom,
+            #[cfg(debug_assertions)]
+            god_view: false,
             server_to_local_id: HashMap::new(),
-            rx_packet: rx,
-            player_id,
-            ping_timer: Timer::new(Duration::from_secs(1)),
+            ping_timer: Timer::new(config.ping_interval),
+            ping_sent: None,
+            clock: ClockSync::new(),
             player_pos_timer: Timer::new(Duration::from_millis(50)),
             timeout_timer: Timer::new(TIMEOUT),
-            shot_cooldown: Cooldown::new(Duration::from_millis(250)),
-        }
+            shot_cooldown: Cooldown::new(config.weapon.cooldown),
+            next_correlation: 0,
+            predicted_shots: HashMap::new(),
+            world: None,
+            waiting_for_wave: false,
+            ready: false,
+        };
+
+        // the address from the command line, the menu only comes up if that falls through
+        game.connect();
+        game
     }
 
     fn tick(&mut self, dt: Duration, wm: &mut WindowManager) {
+        let dt = self.frame_timer.smooth(dt);
+        self.handle_commands();
+        self.tick_fps(dt);
+
+        match &self.state {
+            GameState::Menu => (),
+            GameState::Connecting(rx) => match rx.try_recv() {
+                Ok(Ok((sock, world))) => self.start_playing(sock, world),
+                Ok(Err(e)) => {
+                    self.disconnect(format!("couldn't connect to {}: {}", self.address, e))
+                }
+                Err(TryRecvError::Disconnected) => self.disconnect("connect thread died".into()),
+                Err(TryRecvError::Empty) => (),
+            },
+            GameState::Playing(_) | GameState::GameOver(..) => self.tick_playing(dt),
+            GameState::Disconnected(reason) => {
+                println!("client - disconnected: {}", reason);
+                println!("client - type `connect <ip[:port]>` to join a server");
+                self.state = GameState::Menu;
+            }
+        }
+    }
+
+    fn draw(&mut self, ctx: &'c DrawContext, wm: &mut WindowManager) {
+        render::clear();
+        // TODO: draw the menu and connection status once there's text rendering
+        if let GameState::Playing(_) | GameState::GameOver(..) = self.state {
+            self.ents.render(&self.shader, self.view());
+        }
+        if let GameState::Playing(_) = self.state {
+            self.draw_threats();
+        }
+    }
+}
+
+impl<'e, 's: 'e> Game<'e, 's> {
+    fn handle_commands(&mut self) {
+        while let Ok(cmd) = self.commands.try_recv() {
+            match cmd {
+                ClientCommand::Connect(address) => match self.state {
+                    GameState::Menu => {
+                        self.address = address;
+                        self.connect();
+                    }
+                    _ => println!("client - already connected to {}", self.address),
+                },
+                ClientCommand::Ready => match &self.state {
+                    GameState::Playing(conn) if self.waiting_for_wave => {
+                        conn.sock
+                            .send(Packet::new(OpCode::Ready, socket::NoData))
+                            .unwrap();
+                        self.ready = true;
+                    }
+                    GameState::Playing(_) => println!("client - the wave is already on"),
+                    _ => println!("client - not in a round"),
+                },
+                ClientCommand::Fps => self.show_fps = !self.show_fps,
+                ClientCommand::ZoomIn => self.zoom *= ZOOM_STEP,
+                ClientCommand::ZoomOut => self.zoom /= ZOOM_STEP,
+                #[cfg(debug_assertions)]
+                ClientCommand::God => {
+                    self.god_view = !self.god_view;
+                    if self.god_view {
+                        self.print_labels();
+                    }
+                }
+            }
+        }
+    }
+
+    fn tick_fps(&mut self, dt: Duration) {
+        // no text rendering yet, so the frame rate goes to stdout
+        if self.fps_timer.tick(dt) && self.show_fps {
+            println!(
+                "client - {:.0} fps ({:.1} ms)",
+                self.frame_timer.fps(),
+                self.frame_timer.average().as_secs_f32() * 1000.0
+            );
+            let stats = self.ents.interpolation_stats();
+            println!(
+                "client - {} network targets buffered, {} underruns",
+                stats.buffered, stats.underruns
+            );
+        }
+    }
+
+    /// the wave starts once every player has voted for it
+    fn wait_for_wave(&mut self) {
+        self.waiting_for_wave = true;
+        self.ready = false;
+        println!("client - type `ready` to start the wave");
+    }
+
+    fn connect(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        let address = self.address;
+        let retries = self.config.connect_retries;
+        thread::spawn(move || connect(address, retries, tx));
+        self.state = GameState::Connecting(rx);
+    }
+
+    fn start_playing(&mut self, sock: Client, world: WorldInfo) {
+        // a different server may have a different world
+        if self.world != Some(world) {
+            self.ents.retain(|_, e| !e.kind().is_static());
+            self.ents.create_forest(world);
+            if world.local_statics {
+                self.ents.plant_trees(world, SpriteName::Forest);
+            }
+            self.world = Some(world);
+        }
+
+        let sock = Arc::new(sock);
+        let sock_ = sock.clone();
+        let (tx, rx_packet) = mpsc::channel();
+        let (ptx, prx) = mpsc::channel();
+        let (ktx, krx) = mpsc::channel();
+        thread::spawn(move || recv_loop(sock_, tx));
+
+        // a new server has its own clock
+        self.clock = ClockSync::new();
+        self.ping_sent = None;
+
+        // before the deer's spawn, a server that still has our deer moves the new one onto it
+        sock.send(Identify { token: self.token }).unwrap();
+        let player_id = self.ents.spawn_player(krx, ptx, &sock);
+        if !self.config.simulate_remote {
+            self.ents.set_local_owner(Some(player_id));
+        }
+
+        self.timeout_timer.reset();
+        self.wait_for_wave();
+        self.state = GameState::Playing(Connection {
+            sock,
+            rx_packet,
+            player_id,
+            prx,
+            ktx,
+        });
+    }
+
+    /// drops everything the server gave us and goes back to the menu
+    fn disconnect(&mut self, reason: String) {
+        self.drop_connection();
+        self.state = GameState::Disconnected(reason);
+    }
+
+    /// drops everything the server gave us and tries the same server again
+    /// the server hands our deer back if we make it before it gives up on us
+    fn reconnect(&mut self) {
+        println!("client - lost connection to {}, reconnecting", self.address);
+        self.drop_connection();
+        self.connect();
+    }
+
+    fn drop_connection(&mut self) {
+        if let GameState::Playing(conn) | GameState::GameOver(conn, _) = &self.state {
+            self.ents.destroy(conn.player_id);
+        }
+        for (_, lid) in self.server_to_local_id.drain() {
+            self.ents.destroy(lid);
+        }
+        self.ents.set_local_owner(None);
+    }
+
+    fn tick_playing(&mut self, dt: Duration) {
+        let (GameState::Playing(conn) | GameState::GameOver(conn, _)) = &self.state else {
+            return;
+        };
+        let playing = matches!(self.state, GameState::Playing(_));
+        // None means a new round started
+        let mut round_change = None;
         let dtf = dt.as_secs_f32();
 
+        let received = conn.rx_packet.try_recv();
+        match &received {
+            Ok(p) if socket::OpCode::Bye == p.opcode() => {
+                self.disconnect("kicked by the server".into());
+                return;
+            }
+            Err(TryRecvError::Disconnected) => {
+                self.reconnect();
+                return;
+            }
+            _ => (),
+        }
+
+        // reset on every pong, so this only runs out once the server stops answering pings
+        let pong = matches!(&received, Ok(p) if socket::OpCode::Pong == p.opcode());
+        if !pong && self.timeout_timer.tick(dt) {
+            println!("client - no pong for {:?}", TIMEOUT);
+            self.reconnect();
+            return;
+        }
+
         if self.ping_timer.tick(dt) {
             let packet = Packet::new(socket::OpCode::Ping, socket::NoData);
-            self.sock.send(packet).unwrap();
+            conn.sock.send(packet).unwrap();
+            self.ping_sent = Some(Instant::now());
             // println!("client - ping")
-        }
 
-        // if self.timeout_timer.tick(dt) {
-        //     panic!("Server timed out");
-        // }
+            // udp can lose the vote, so it rides along with the pings until the wave starts
+            if self.waiting_for_wave && self.ready {
+                let ready = Packet::new(OpCode::Ready, socket::NoData);
+                conn.sock.send(ready).unwrap();
+            }
+        }
 
-        if let Ok(p) = self.rx_packet.try_recv() {
+        if let Ok(p) = received {
             if socket::OpCode::Pong == p.opcode() {
                 self.timeout_timer.reset();
                 // println!("client - pong")
+                let data = p.data();
+                if let (Some(sent), Some(millis)) = (self.ping_sent.take(), data.get(..4)) {
+                    let millis = u32::from_be_bytes(millis.try_into().unwrap());
+                    let server_time = Duration::from_millis(millis as _);
+                    self.clock.pong(sent, Instant::now(), server_time);
+                }
             } else {
                 match p.opcode() {
                     OpCode::EntitySpawn => {
                         let e = EntitySpawn::try_from(p).unwrap();
-
-                        let sprite = match e.kind {
-                            common::EntityKind::Tile => SpriteName::Tile,
-                            common::EntityKind::Forest => SpriteName::Forest,
-                            common::EntityKind::Player => SpriteName::Deer,
-                            common::EntityKind::PlayerProjectile => SpriteName::Spit,
-                            common::EntityKind::Enemy => SpriteName::Hunter,
-                        };
-
-                        let lid = self
-                            .ents
-                            .spawn(e.pos, e.scale, e.speed, 0.0, e.dir, sprite, e.kind);
-                        self.server_to_local_id.insert(e.id, lid);
-                        // println!("Spawning entity ({:?}) sid=[{}], lid=[{}]", e.kind, e.id, lid);
+                        // acked even when we already have it, the last ack may have been lost
+                        self.ents.apply_spawn(&mut self.server_to_local_id, e);
+                        conn.sock.send(SpawnAck { id: e.id }).unwrap();
+                        // println!("Spawning entity ({:?}) sid=[{}]", e.kind, e.id);
                     }
                     OpCode::EntityUpdate => {
                         let e = EntityUpdate::try_from(p).unwrap();
-                        let lid = self.server_to_local_id[&e.id];
-                        // ents.set_position(lid, e.pos);
-                        let d = e.pos - self.ents.get(lid).pos();
-                        self.ents.get_mut(lid).set_direction(d);
+                        if e.id == 0 {
+                            // the server moved our deer, like pushing it off another one
+                            self.ents.set_position(conn.player_id, e.pos);
+                        } else if let Some(&lid) = self.server_to_local_id.get(&e.id) {
+                            let ent = self.ents.get_mut(lid);
+                            ent.set_network_target(e.pos);
+                            ent.set_network_rotation(e.rotation);
+                        }
                     }
                     OpCode::EntityDestroy => {
                         let e = EntityDestroy::try_from(p).unwrap();
                         // println!("client: entity destroy sid=[{}]", e.id);
-                        let lid = self.server_to_local_id[&e.id];
-                        self.ents.destroy(lid);
-                    } // _ => (),
+                        // spit we predicted but the server never acked has no server id
+                        if let Some(lid) = self.server_to_local_id.remove(&e.id) {
+                            self.ents.despawn(lid, self.config.despawn_fade);
+                        }
+                    }
+                    OpCode::Collision => {
+                        let e = Collision::try_from(p).unwrap();
+                        spawn_hit_effect(&mut self.ents, e.point);
+                    }
+                    OpCode::GameOver => {
+                        let e = GameOver::try_from(p).unwrap();
+                        round_change = Some(Some(e.reason));
+                    }
+                    OpCode::WorldReset => round_change = Some(None),
+                    OpCode::PowerupApplied => {
+                        let e = PowerupApplied::try_from(p).unwrap();
+                        println!("client - picked up {:?}", e.powerup);
+                        self.ents
+                            .get_mut(conn.player_id)
+                            .add_effect(Effect::Powerup(e.powerup), e.duration);
+                    }
+                    OpCode::FireAck => {
+                        let e = FireAck::try_from(p).unwrap();
+                        if let Some(lid) = self.predicted_shots.remove(&e.correlation) {
+                            self.server_to_local_id.insert(e.id, lid);
+                        }
+                    }
+                    OpCode::WaveStart => {
+                        println!("client - wave start");
+                        self.waiting_for_wave = false;
+                        self.ready = false;
+                    }
+                    OpCode::Stun => {
+                        let e = Stun::try_from(p).unwrap();
+                        if let Some(&lid) = self.server_to_local_id.get(&e.id) {
+                            let hunter = self.ents.get_mut(lid);
+                            if e.duration.is_zero() {
+                                hunter.remove_effect(Effect::Stun);
+                            } else {
+                                hunter.add_effect(Effect::Stun, e.duration);
+                            }
+                        }
+                    }
+                    // only ever sent to the server, or debug tooling
+                    OpCode::SpawnAck
+                    | OpCode::ListEntities
+                    | OpCode::EntityList
+                    | OpCode::Ready
+                    | OpCode::Fire
+                    | OpCode::Input
+                    | OpCode::Identify => (),
                 }
             }
         }
@@ -216,52 +618,172 @@ impl<'e, 's: 'e, 'c: 's> GameLoop<'c> for Game<'e, 's> {
                 let s = self.get_key(Key::S);
                 let d = self.get_key(Key::D);
                 let space = self.get_key(Key::Space);
-                self.ktx.send((w, a, s, d)).unwrap();
+                conn.ktx.send((w, a, s, d)).unwrap();
         */
         let space = false;
 
         self.ents.tick(dtf);
+        self.ents.mark_stale(self.config.stale_after);
 
         let send_player_pos = self.player_pos_timer.tick(dt);
 
-        let player_pos = self.prx.recv().unwrap();
+        let player_pos = conn.prx.recv().unwrap();
+        self.camera.follow(player_pos);
+
+        self.zoom = self.zoom.clamp(self.config.min_zoom, self.config.max_zoom);
+        let t = (dtf * ZOOM_RATE).min(1.0);
+        self.camera.zoom += t * (self.zoom - self.camera.zoom);
+
         if send_player_pos {
             let p = EntityUpdate {
                 id: 0,
                 pos: player_pos,
+                rotation: self.ents.get(conn.player_id).rotation(),
             };
-            self.sock.send(p).unwrap();
+            conn.sock.send(p).unwrap();
         }
 
-        if self.shot_cooldown.tick(dt) && space {
-            let up = Vec2::new(0.0, 1.0);
-            let scale = 6.0;
-            let speed = 30.0;
-            self.ents.spawn(
-                player_pos,
-                scale,
-                speed,
-                0.0,
-                up,
-                SpriteName::Spit,
-                EntityKind::PlayerProjectile,
-            );
-            let projectile_spawn = EntitySpawn {
-                id: 0,
-                kind: EntityKind::PlayerProjectile,
-                pos: player_pos,
-                scale,
-                speed,
-                dir: up,
-            };
-            self.sock.send(projectile_spawn).unwrap();
+        for effect in self.ents.get_mut(conn.player_id).take_expired_effects() {
+            if let Effect::Powerup(powerup) = effect {
+                println!("client - {:?} wore off", powerup);
+            }
+        }
+        let player = self.ents.get(conn.player_id);
+        let modifiers = Modifiers {
+            fire_rate: if player.has_effect(Effect::Powerup(Powerup::RapidFire)) {
+                RAPID_FIRE_RATE
+            } else {
+                1
+            },
+            spread: player.has_effect(Effect::Powerup(Powerup::SpreadShot)),
+        };
+
+        // rapid fire runs the cooldown faster rather than shortening it
+        if self.shot_cooldown.tick(dt * modifiers.fire_rate) && space && playing {
+            // the server spawns the real spit, ours stands in until it acks
+            let weapon = self.config.weapon;
+            let aim = Vec2::new(0.0, 1.0);
+            let correlation = self.next_correlation;
+            let dirs = shot_directions(aim, modifiers.spread);
+            for (i, &dir) in dirs.iter().enumerate() {
+                let lid = self.ents.spawn(
+                    player_pos,
+                    weapon.projectile_scale,
+                    weapon.projectile_speed,
+                    0.0,
+                    dir,
+                    SpriteName::Spit,
+                    EntityKind::PlayerProjectile,
+                );
+                let spit = self.ents.get_mut(lid);
+                spit.set_ttl(weapon.projectile_ttl);
+                spit.set_owner(Some(conn.player_id));
+                self.predicted_shots
+                    .insert(correlation.wrapping_add(i as u16), lid);
+            }
+            self.next_correlation = correlation.wrapping_add(dirs.len() as u16);
+            conn.sock
+                .send(Fire {
+                    correlation,
+                    dir: aim,
+                })
+                .unwrap();
             self.shot_cooldown.enable();
         }
+        // shots the server turned down or never answered
+        let ents = &self.ents;
+        self.predicted_shots.retain(|_, lid| ents.contains(*lid));
+        // like spit that ran out here before the server's destroy arrived
+        self.server_to_local_id.retain(|_, lid| ents.contains(*lid));
+
+        if self.position_log.as_mut().is_some_and(|t| t.tick(dt) > 0) {
+            self.log_positions();
+        }
+        if let Some(reason) = round_change {
+            self.set_game_over(reason);
+        }
     }
 
-    fn draw(&mut self, ctx: &'c DrawContext, wm: &mut WindowManager) {
-        render::clear();
-        self.ents.render(&self.shader);
+    /// the client side of the desync log, where we think the server's entities are
+    fn log_positions(&mut self) {
+        let mut ids = self.server_to_local_id.iter().collect::<Vec<_>>();
+        ids.sort();
+        for (&sid, &lid) in ids {
+            let e = self.ents.get(lid);
+            if !e.kind().is_static() {
+                let time = self.clock.server_time(Instant::now());
+                common::log_position(self.position_seq, time, sid, e.kind(), e.pos());
+            }
+        }
+        self.position_seq += 1;
+    }
+
+    /// moves the connection between playing and the game over screen
+    fn set_game_over(&mut self, reason: Option<GameOverReason>) {
+        // TODO: show this on screen once there's text rendering
+        match reason {
+            Some(reason) => println!("client - game over: {:?}", reason),
+            None => {
+                println!("client - new round");
+                self.wait_for_wave();
+            }
+        }
+
+        self.state = match std::mem::replace(&mut self.state, GameState::Menu) {
+            GameState::Playing(conn) | GameState::GameOver(conn, _) => match reason {
+                Some(reason) => GameState::GameOver(conn, reason),
+                None => GameState::Playing(conn),
+            },
+            state => state,
+        };
+    }
+
+    /// marks the closest hunters that are off screen at the edge, facing their way
+    fn draw_threats(&self) {
+        // TODO: a proper arrow sprite, a small hunter will do for now
+        let center = self.camera.center;
+        let mut renderer = self.ents.sprite_renderer(&self.shader);
+        let hunters = self
+            .ents
+            .nearest_of_kind(center, EntityKind::Enemy, THREAT_MARKERS);
+        for (_, pos) in hunters {
+            let screen = self.camera.to_ndc(pos);
+            let far = screen.x.abs().max(screen.y.abs());
+            if far < 1.0 {
+                continue;
+            }
+
+            let offset = pos - center;
+            let marker = Transform {
+                pos: center + (0.9 / far) * offset,
+                rotation: offset.angle(),
+                scale: Vec2::new(3.0, 3.0),
+            };
+            renderer.draw(SpriteName::Hunter, self.view(), marker);
+        }
+    }
+
+    fn view(&self) -> Mat3 {
+        #[cfg(debug_assertions)]
+        if self.god_view {
+            return Camera::default().view();
+        }
+
+        self.camera.view()
+    }
+
+    /// no text rendering yet, so god view labels go to stdout
+    #[cfg(debug_assertions)]
+    fn print_labels(&self) {
+        for (id, e) in self.ents.iter().filter(|e| e.1.kind() != EntityKind::Tile) {
+            let pos = e.pos();
+            println!(
+                "god view - {} at ({}, {})",
+                self.ents.label(id),
+                pos.x,
+                pos.y
+            );
+        }
     }
 }
 
@@ -274,20 +796,44 @@ impl Game {
 */
 
 fn main() {
-    let args = env::args().collect::<Vec<_>>();
-    let mut client_ip = Ipv4Addr::LOCALHOST;
-    let default_port = 7777;
-    let force_server = true;
-    if force_server {
-        thread::spawn(move || server::run(default_port));
-    } else if args.len() > 1 {
-        match args[1].as_str() {
-            "server" => {
-                thread::spawn(move || server::run(default_port));
+    OpCode::register_names();
+
+    // deer-defense [--host | --server] [--map file] [ip[:port]]
+    // --host plays on a server hosted in this process
+    // --server runs a dedicated server without a window
+    // --map builds the server's walls from a map file instead of planting a forest
+    let mut config = GameConfig::default();
+    let mut host = false;
+    let mut dedicated = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" | "server" => host = true,
+            "--server" => dedicated = true,
+            // the config lives for the whole run, so the path can too
+            "--map" => {
+                let path = args.next().expect("--map needs a file");
+                config.map = Some(Box::leak(path.into_boxed_str()));
+            }
+            address => {
+                config.server_address = config::parse_address(address).expect("Expected IP address")
             }
-            ip => client_ip = Ipv4Addr::from_str(ip).expect("Expected IP address"),
         }
     }
+    if host {
+        // only the port matters when we're the server
+        config.server_address.set_ip(Ipv4Addr::LOCALHOST.into());
+    }
+    config::init(config);
+
+    let port = config.server_address.port();
+    if dedicated {
+        server::run(port, config, true);
+        return;
+    }
+    if host {
+        thread::spawn(move || server::run(port, config, false));
+    }
 
     let window = WindowManager::new(1200, 1200, "Deer Defense");
     let mut engine = Engine::new(window);
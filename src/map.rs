@@ -0,0 +1,64 @@
+//! authored maps: one line of text per row of cells, `#` is a wall and anything else is open
+//! the grid is centered on the base, row 0 along the top
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use engine_2d::math::Vec2;
+
+/// world units across one map cell, a wall fills its cell
+pub const MAP_CELL: f32 = 4.0;
+
+pub struct Map {
+    /// cell centers, in world space
+    walls: Vec<Vec2>,
+}
+
+impl Map {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let rows = text.lines().collect::<Vec<_>>();
+        let height = rows.len() as f32;
+        let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0) as f32;
+        let walls = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(row, line)| {
+                line.chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == '#')
+                    .map(move |(col, _)| {
+                        let x = (col as f32 + 0.5 - width / 2.0) * MAP_CELL;
+                        let y = (height / 2.0 - row as f32 - 0.5) * MAP_CELL;
+                        Vec2::new(x, y)
+                    })
+            })
+            .collect();
+
+        Self { walls }
+    }
+
+    pub fn walls(&self) -> &[Vec2] {
+        &self.walls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walls_are_centered_on_the_base() {
+        let map = Map::parse("#..\n...\n..#\n");
+        let walls = map.walls().iter().map(|w| (w.x, w.y)).collect::<Vec<_>>();
+        assert_eq!(walls, [(-MAP_CELL, MAP_CELL), (MAP_CELL, -MAP_CELL)]);
+
+        // short rows are open past their end
+        assert_eq!(Map::parse("#\n...").walls().len(), 1);
+        assert!(Map::parse("").walls().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use engine_2d::math::Vec2;
+
+/// eases between angles in degrees, going the short way around
+pub fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    let diff = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+    (from + diff * t).rem_euclid(360.0)
+}
+
+/// helpers the engine's Vec2 doesn't have, built on its existing ops
+pub trait Vec2Ext {
+    fn distance(self, other: Vec2) -> f32;
+    /// `t` of the way from self to `to`, unclamped
+    fn lerp(self, to: Vec2, t: f32) -> Vec2;
+    /// same direction, at most `max` long
+    fn clamp_length(self, max: f32) -> Vec2;
+    /// each component clamped separately
+    fn clamp(self, min: Vec2, max: Vec2) -> Vec2;
+    /// turned clockwise by `degrees`
+    fn rotate(self, degrees: f32) -> Vec2;
+    /// clockwise degrees to turn self by to face along `to`, in [-180, 180]
+    fn angle_to(self, to: Vec2) -> f32;
+}
+
+impl Vec2Ext for Vec2 {
+    fn distance(self, other: Vec2) -> f32 {
+        (self - other).len2().sqrt()
+    }
+
+    fn lerp(self, to: Vec2, t: f32) -> Vec2 {
+        self + t * (to - self)
+    }
+
+    fn clamp_length(self, max: f32) -> Vec2 {
+        if self.len2() > max * max {
+            max * self.normalize()
+        } else {
+            self
+        }
+    }
+
+    fn clamp(self, min: Vec2, max: Vec2) -> Vec2 {
+        Vec2::new(self.x.clamp(min.x, max.x), self.y.clamp(min.y, max.y))
+    }
+
+    fn rotate(self, degrees: f32) -> Vec2 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Vec2::new(self.x * cos + self.y * sin, self.y * cos - self.x * sin)
+    }
+
+    fn angle_to(self, to: Vec2) -> f32 {
+        let cross = self.x * to.y - self.y * to.x;
+        let dot = self.x * to.x + self.y * to.y;
+        -cross.atan2(dot).to_degrees()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn assert_vec_near(a: Vec2, b: Vec2) {
+        assert!(a.distance(b) < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn distance_and_lerp() {
+        let (a, b) = (Vec2::new(1.0, 1.0), Vec2::new(4.0, 5.0));
+        assert_near(a.distance(b), 5.0);
+        assert_vec_near(a.lerp(b, 0.5), Vec2::new(2.5, 3.0));
+        // unclamped
+        assert_vec_near(a.lerp(b, 2.0), Vec2::new(7.0, 9.0));
+    }
+
+    #[test]
+    fn clamp_length_keeps_the_direction() {
+        let v = Vec2::new(3.0, 4.0);
+        assert_vec_near(v.clamp_length(10.0), v);
+        assert_vec_near(v.clamp_length(2.5), Vec2::new(1.5, 2.0));
+        let min = Vec2::new(-1.0, -1.0);
+        assert_vec_near(v.clamp(min, Vec2::new(2.0, 8.0)), Vec2::new(2.0, 4.0));
+    }
+
+    #[test]
+    fn rotate_and_angle_to_go_clockwise() {
+        let right = Vec2::new(1.0, 0.0);
+        let down = Vec2::new(0.0, -1.0);
+        assert_vec_near(right.rotate(90.0), down);
+        assert_vec_near(right.rotate(-90.0), Vec2::new(0.0, 1.0));
+        assert_near(right.angle_to(down), 90.0);
+        assert_near(down.angle_to(right), -90.0);
+        assert_near(right.angle_to(Vec2::new(-1.0, 0.0)).abs(), 180.0);
+        // turning by angle_to faces the target
+        let to = Vec2::new(-2.0, 3.0);
+        let turned = right.rotate(right.angle_to(to));
+        assert_vec_near(turned, to.normalize());
+    }
+
+    #[test]
+    fn lerp_angle_goes_the_short_way_around() {
+        assert_near(lerp_angle(350.0, 10.0, 0.25), 355.0);
+        assert_near(lerp_angle(350.0, 10.0, 0.5), 0.0);
+        assert_near(lerp_angle(350.0, 10.0, 1.0), 10.0);
+        assert_near(lerp_angle(10.0, 350.0, 0.5), 0.0);
+        assert_near(lerp_angle(0.0, 90.0, 0.5), 45.0);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use engine_2d::math::Mat3;
+use engine_2d::math::Vec2;
+use engine_2d::render::shader::Shader;
+use engine_2d::render::sprite::ISprite;
+use engine_2d::render::sprite::Sprite;
+
+use crate::common::SpriteName;
+
+/// where a sprite sits in the world, kept apart from the engine's matrix so draw calls can be
+/// compared
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub pos: Vec2,
+    /// degrees
+    pub rotation: f32,
+    /// a negative x mirrors the sprite
+    pub scale: Vec2,
+}
+
+impl Transform {
+    pub fn matrix(self) -> Mat3 {
+        Mat3::translate(self.pos) * Mat3::rotate(self.rotation) * Mat3::scale(self.scale)
+    }
+
+    /// where `matrix` takes `point`, scaled, then rotated, then moved
+    pub fn apply(self, point: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let x = point.x * self.scale.x;
+        let y = point.y * self.scale.y;
+        self.pos + Vec2::new(x * cos - y * sin, x * sin + y * cos)
+    }
+}
+
+/// where entities send their draw calls, so rendering can run without a gl context
+pub trait Renderer {
+    /// draws `sprite` on the unit quad placed by `transform`, then mapped to the screen by `view`
+    fn draw(&mut self, sprite: SpriteName, view: Mat3, transform: Transform);
+}
+
+/// draws loaded sprites with the engine, sprites that were never loaded are skipped
+pub struct SpriteRenderer<'r, 's> {
+    shader: &'r Shader<'s>,
+    sprites: &'r HashMap<SpriteName, Sprite<'s>>,
+}
+
+impl<'r, 's> SpriteRenderer<'r, 's> {
+    pub fn new(shader: &'r Shader<'s>, sprites: &'r HashMap<SpriteName, Sprite<'s>>) -> Self {
+        Self { shader, sprites }
+    }
+}
+
+impl<'r, 's> Renderer for SpriteRenderer<'r, 's> {
+    fn draw(&mut self, sprite: SpriteName, view: Mat3, transform: Transform) {
+        if let Some(sprite) = self.sprites.get(&sprite) {
+            sprite.draw(self.shader, view * transform.matrix());
+        }
+    }
+}
+
+/// keeps every draw call instead of drawing, so transforms can be checked without a window
+/// the view is the camera's business, so only world space is kept
+#[derive(Default)]
+pub struct RecordingRenderer {
+    pub calls: Vec<(SpriteName, Transform)>,
+}
+
+impl Renderer for RecordingRenderer {
+    fn draw(&mut self, sprite: SpriteName, _view: Mat3, transform: Transform) {
+        self.calls.push((sprite, transform));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_scales_then_rotates_then_moves() {
+        let transform = Transform {
+            pos: Vec2::new(10.0, 20.0),
+            rotation: 90.0,
+            scale: Vec2::new(-2.0, 3.0),
+        };
+        let p = transform.apply(Vec2::new(1.0, 1.0));
+        // (1, 1) scales to (-2, 3), turns to (-3, -2) and lands at (7, 18)
+        assert!(
+            (p.x - 7.0).abs() < 1e-5 && (p.y - 18.0).abs() < 1e-5,
+            "{:?}",
+            p
+        );
+    }
+}
@@ -9,9 +9,13 @@
 */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fmt::Formatter;
 use std::io::ErrorKind;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
@@ -25,20 +29,115 @@ use engine_2d::time::Timer;
 use rand::thread_rng;
 use rand::Rng;
 
+use crate::common::log_position;
+use crate::common::shot_directions;
+use crate::common::Collision;
 use crate::common::EntityDestroy;
 use crate::common::EntityKind;
+use crate::common::EntityList;
+use crate::common::EntityListing;
 use crate::common::EntitySpawn;
 use crate::common::EntityUpdate;
+use crate::common::Fire;
+use crate::common::FireAck;
+use crate::common::GameOver;
+use crate::common::GameOverReason;
+use crate::common::Identify;
+use crate::common::Input;
 use crate::common::OpCode;
+use crate::common::Powerup;
+use crate::common::PowerupApplied;
+use crate::common::SpawnAck;
 use crate::common::SpriteName;
+use crate::common::Stun;
+use crate::common::WorldInfo;
+use crate::common::RAPID_FIRE_RATE;
 use crate::common::TIMEOUT;
+use crate::config::EnemyConfig;
+use crate::config::GameConfig;
+use crate::console;
+use crate::console::Command;
 use crate::entities;
+use crate::entities::Effect;
+use crate::entities::Entity;
 use crate::entities::WORLD_SIZE;
+use crate::map::Map;
+use crate::map::MAP_CELL;
+use crate::math_ext::Vec2Ext;
 use crate::socket;
 use crate::socket::Error;
 use crate::socket::NoData;
 use crate::socket::Packet;
 use crate::socket::Server;
+use crate::timing::Interval;
+
+/// traffic is reported per second over the stats interval
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// how long clients see the game over screen before the next round
+const ROUND_RESET_DELAY: Duration = Duration::from_secs(5);
+/// percentage of the shot cooldown that has to pass between Fire requests
+const FIRE_SLACK_PERCENT: u32 = 80;
+/// uncollected pickups vanish after this long
+const PICKUP_TTL: Duration = Duration::from_secs(10);
+/// pickups dropped in a tree get moved at most this far to where a deer can reach them
+const PICKUP_SEARCH: f32 = 12.0;
+/// how long a hunter hit by stun shot spit stands still
+const STUN_DURATION: Duration = Duration::from_secs(2);
+/// degrees a hunter with no aggression at all may stray from the base each tick
+const MAX_WANDER_ANGLE: f32 = 180.0;
+/// the biggest and fastest a client may spawn anything, roomy enough for its deer
+const MAX_CLIENT_SCALE: f32 = 8.0;
+const MAX_CLIENT_SPEED: f32 = 32.0;
+/// how far from where its client put it a deer may be moved to keep it out of the trees
+const PLAYER_SPAWN_SEARCH: f32 = 32.0;
+/// how far ahead hunters look for walls to walk around
+const WALL_LOOKAHEAD: f32 = 8.0;
+/// homing spit only chases hunters within this many degrees either side of its heading
+const HOMING_CONE: f32 = 45.0;
+/// degrees per second homing spit can turn, slow enough that it can still miss
+const HOMING_TURN_RATE: f32 = 90.0;
+
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    pub packets_received_per_sec: f32,
+    pub packets_sent_per_sec: f32,
+    pub packets_dropped: u64,
+    pub clients: usize,
+    pub entities: HashMap<EntityKind, usize>,
+}
+
+impl ServerStats {
+    pub fn collect(socket: &Server, ents: &entities::EntityManager, interval: Duration) -> Self {
+        let traffic = socket.take_traffic();
+        let secs = interval.as_secs_f32();
+        let mut entities = HashMap::new();
+        for (_, e) in ents.iter() {
+            *entities.entry(e.kind()).or_default() += 1;
+        }
+
+        Self {
+            packets_received_per_sec: traffic.received as f32 / secs,
+            packets_sent_per_sec: traffic.sent as f32 / secs,
+            packets_dropped: traffic.dropped,
+            clients: socket.client_count(),
+            entities,
+        }
+    }
+}
+
+impl Display for ServerStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rx {:.1}/s, tx {:.1}/s, dropped {}, clients {}, entities {:?}",
+            self.packets_received_per_sec,
+            self.packets_sent_per_sec,
+            self.packets_dropped,
+            self.clients,
+            self.entities
+        )
+    }
+}
 
 fn broadcast(
     packet: Packet,
@@ -47,243 +146,2439 @@ fn broadcast(
     clients: impl Iterator<Item = SocketAddr>,
 ) {
     let but = but.unwrap_or((Ipv4Addr::UNSPECIFIED, 0).into());
+    let bytes = packet.into_bytes().unwrap();
     clients.filter(|a| a != &but).for_each(|a| {
-        socket.send(packet.clone(), a).unwrap();
+        socket.send_bytes(&bytes, a).unwrap();
     })
 }
 
+/// client timeouts are this many times their ping interval or rtt, whichever is worse
+const TIMEOUT_FACTOR: u32 = 4;
+/// for clients on a fast link, raised to cover a few pings when the interval is longer
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// times a client out based on how often we actually hear from it
+pub struct Liveness {
+    timer: Timer,
+    last_heard: Instant,
+    interval: Duration,
+    rtt: Duration,
+    /// the shortest timeout, enough for a few of our pings to be answered
+    floor: Duration,
+    /// when the ping still waiting for its pong went out to this client
+    ping_sent: Option<Instant>,
+}
+
+impl Liveness {
+    fn new(min_timeout: Duration) -> Self {
+        Self {
+            timer: Timer::new(TIMEOUT),
+            last_heard: Instant::now(),
+            interval: Duration::ZERO,
+            rtt: Duration::ZERO,
+            floor: min_timeout.clamp(MIN_TIMEOUT, MAX_TIMEOUT),
+            ping_sent: None,
+        }
+    }
+
+    /// any packet from the client counts as a sign of life
+    fn heard(&mut self) {
+        let now = Instant::now();
+        let gap = now - self.last_heard;
+        self.last_heard = now;
+        // smoothed like tcp's srtt
+        self.interval = (self.interval * 7 + gap) / 8;
+        self.timer = Timer::new(self.timeout());
+    }
+
+    /// a lost pong's ping is forgotten, the rtt is measured from the newest one
+    fn pinged(&mut self) {
+        self.ping_sent = Some(Instant::now());
+    }
+
+    /// pongs nobody is waiting for don't count towards the rtt
+    fn pong(&mut self) {
+        if let Some(sent) = self.ping_sent.take() {
+            self.rtt = (self.rtt * 7 + sent.elapsed()) / 8;
+        }
+    }
+
+    pub fn rtt(&self) -> Duration {
+        self.rtt
+    }
+
+    pub fn timeout(&self) -> Duration {
+        (self.interval.max(self.rtt) * TIMEOUT_FACTOR).clamp(self.floor, MAX_TIMEOUT)
+    }
+
+    /// true once the client has been silent for too long
+    fn tick(&mut self, dt: Duration) -> bool {
+        self.timer.tick(dt)
+    }
+}
+
+/// everything the server tracks for one connected client
+pub struct ClientConn {
+    liveness: Liveness,
+    /// set once the client spawns its deer
+    player_id: Option<i32>,
+    /// wants the wave to start
+    ready: bool,
+    last_fire: Option<Instant>,
+    /// the newest input, movement is client reported until the first one arrives
+    input: Option<Input>,
+    /// entities close enough to its deer that the client gets their updates
+    in_range: HashSet<i32>,
+    /// entities it stopped getting updates for, respawned when they come back in range
+    culled: HashSet<i32>,
+    /// sent by the client, its deer outlives a timeout under this
+    token: Option<u64>,
+}
+
+impl ClientConn {
+    fn new(config: &GameConfig) -> Self {
+        Self {
+            liveness: Liveness::new(config.min_timeout()),
+            player_id: None,
+            ready: false,
+            last_fire: None,
+            input: None,
+            in_range: HashSet::new(),
+            culled: HashSet::new(),
+            token: None,
+        }
+    }
+}
+
+/// how long a timed out client's deer waits for it to come back
+const RECONNECT_GRACE: Duration = Duration::from_secs(10);
+
+/// deer whose clients timed out, by the token they identified with
+#[derive(Default)]
+struct Detached {
+    deer: HashMap<u64, (i32, Timer)>,
+}
+
+impl Detached {
+    fn insert(&mut self, token: u64, id: i32) {
+        self.deer.insert(token, (id, Timer::new(RECONNECT_GRACE)));
+    }
+
+    fn take(&mut self, token: u64) -> Option<i32> {
+        self.deer.remove(&token).map(|(id, _)| id)
+    }
+
+    /// the deer whose clients didn't come back in time
+    fn expired(&mut self, dt: Duration) -> Vec<i32> {
+        let mut expired = Vec::new();
+        self.deer.retain(|_, (id, timer)| {
+            let gone = timer.tick(dt);
+            if gone {
+                expired.push(*id);
+            }
+            !gone
+        });
+        expired
+    }
+}
+
+/// how long a client has to ack a spawn before it is resent
+const ACK_WINDOW: Duration = Duration::from_millis(250);
+const MAX_SPAWN_RESENDS: u32 = 3;
+
+struct PendingSpawn {
+    spawn: EntitySpawn,
+    timer: Timer,
+    resends: u32,
+}
+
+/// resends spawns that clients haven't acked, so a lost spawn doesn't leave a missing entity
+#[derive(Default)]
+struct SpawnAcks {
+    pending: HashMap<SocketAddr, HashMap<i32, PendingSpawn>>,
+}
+
+impl SpawnAcks {
+    fn send(&mut self, spawn: EntitySpawn, address: SocketAddr, socket: &Server) {
+        socket.send(spawn, address).unwrap();
+        let pending = PendingSpawn {
+            spawn,
+            timer: Timer::new(ACK_WINDOW),
+            resends: 0,
+        };
+        self.pending
+            .entry(address)
+            .or_default()
+            .insert(spawn.id, pending);
+    }
+
+    fn acked(&mut self, address: SocketAddr, id: i32) {
+        if let Some(pending) = self.pending.get_mut(&address) {
+            pending.remove(&id);
+        }
+    }
+
+    fn tick(
+        &mut self,
+        dt: Duration,
+        socket: &Server,
+        clients: &HashMap<SocketAddr, ClientConn>,
+        ents: &entities::EntityManager,
+    ) {
+        self.pending
+            .retain(|address, _| clients.contains_key(address));
+        for (address, pending) in self.pending.iter_mut() {
+            pending.retain(|id, p| ents.contains(*id) && p.resends < MAX_SPAWN_RESENDS);
+            for p in pending.values_mut() {
+                if p.timer.tick(dt) {
+                    p.resends += 1;
+                    socket.send(p.spawn, *address).unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// broadcasts the packets queued by the entity hooks
+fn flush_events(
+    events: &Receiver<Packet>,
+    socket: &Server,
+    but: Option<SocketAddr>,
+    clients: &HashMap<SocketAddr, ClientConn>,
+    acks: &mut SpawnAcks,
+) {
+    for packet in events.try_iter() {
+        if OpCode::EntitySpawn == packet.opcode() {
+            let spawn = EntitySpawn::try_from(packet).unwrap();
+            for &address in clients.keys().filter(|&&a| Some(a) != but) {
+                acks.send(spawn, address, socket);
+            }
+        } else {
+            broadcast(packet, socket, but, clients.keys().copied());
+        }
+    }
+}
+
+/// what a client needs to know to spawn `e` itself
+fn entity_spawn(id: i32, e: &dyn Entity) -> EntitySpawn {
+    EntitySpawn {
+        id,
+        kind: e.kind(),
+        pos: e.pos(),
+        scale: e.scale(),
+        speed: e.speed(),
+        dir: e.dir(),
+    }
+}
+
+/// queues a spawn/destroy packet for every entity the manager spawns/destroys
+fn register_event_hooks(ents: &mut entities::EntityManager, tx: Sender<Packet>) {
+    let spawn_tx = tx.clone();
+    ents.on_spawn(move |id, e| {
+        // clients build their own tiles from the world seed
+        if e.kind() == EntityKind::Tile {
+            return;
+        }
+        spawn_tx.send(entity_spawn(id, e).into()).unwrap();
+    });
+    ents.on_despawn(move |id, _| {
+        tx.send(EntityDestroy { id }.into()).unwrap();
+    });
+}
+
 fn read_packet_and_update_world(
     socket: &Server,
     rx: &Receiver<(Packet, SocketAddr)>,
-    clients: &mut HashMap<SocketAddr, Timer>,
+    events: &Receiver<Packet>,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
     ents: &mut entities::EntityManager,
-    player_ids: &mut HashMap<SocketAddr, i32>,
+    acks: &mut SpawnAcks,
+    detached: &mut Detached,
+    wave_started: bool,
+    config: &GameConfig,
 ) {
+    // events queued outside a client's packet, like console spawns, are for everyone, the
+    // flush below skips the sender
+    flush_events(events, socket, None, clients, acks);
+
     if let Ok((p, address)) = rx.try_recv() {
+        if socket.is_banned(address.ip()) {
+            // queued before the ban landed
+            return;
+        }
+
         // println!("server-process");
         match clients.get_mut(&address) {
-            Some(timer) => timer.reset(),
+            Some(conn) => conn.liveness.heard(),
             None => {
                 println!("new client joined! {}", address);
-                let now = Timer::new(TIMEOUT);
-                clients.insert(address, now);
+                let mut conn = ClientConn::new(config);
+                // a client that timed out says who it is before anything else, so it
+                // gets its deer back instead of being greeted with it as someone else's
+                if let Ok(Identify { token }) = Identify::try_from(p.clone()) {
+                    conn.token = Some(token);
+                    conn.player_id = detached.take(token).filter(|&id| ents.contains(id));
+                }
+                let resumed = conn.player_id;
+                clients.insert(address, conn);
+                socket.connect(address);
                 // new client / timed out client reconnect
                 // broadcast all entities rn
 
-                for (id, e) in ents.iter() {
-                    let p = EntitySpawn {
-                        id,
-                        pos: e.pos(),
-                        dir: e.dir(),
-                        scale: e.scale(),
-                        kind: e.kind(),
-                        speed: e.speed(),
-                    };
-
+                // clients generate these themselves, walls always come from us
+                let generated = |kind: EntityKind| {
+                    kind == EntityKind::Tile || (config.local_statics && kind == EntityKind::Forest)
+                };
+                let sent = ents
+                    .iter()
+                    .filter(|e| !generated(e.1.kind()) && Some(e.0) != resumed);
+                for (id, e) in sent {
+                    let p = entity_spawn(id, e);
                     // println!("Server: EntitySpawn {:?}", p);
-                    socket.send(p, address).unwrap();
+                    acks.send(p, address, socket);
+                }
+
+                // the client spawned a fresh deer where it thinks deer start
+                if let Some(id) = resumed {
+                    println!("client {} took back its deer {}", address, id);
+                    let deer = ents.get(id);
+                    let moved = EntityUpdate {
+                        id: 0,
+                        pos: deer.pos(),
+                        rotation: deer.rotation(),
+                    };
+                    socket.send(moved, address).unwrap();
                 }
             }
         }
 
         if socket::OpCode::Pong == p.opcode() {
-            clients.get_mut(&address).unwrap().reset();
+            let conn = clients.get_mut(&address).unwrap();
+            conn.liveness.pong();
             // println!("server - pong ({})", address);
+        } else if !OpCode::is_known(p.opcode()) {
+            socket.record_dropped();
         } else {
             match p.opcode() {
                 OpCode::EntitySpawn => {
-                    let mut e = EntitySpawn::try_from(p).unwrap();
-                    let id = ents.spawn(
-                        e.pos,
-                        e.scale,
-                        e.speed,
-                        0.0,
-                        e.dir,
-                        SpriteName::None,
-                        e.kind,
-                    );
-                    e.id = id;
-
-                    if e.kind == EntityKind::Player {
-                        player_ids.insert(address, id);
-                        player_ids[&address];
+                    let Ok(mut e) = EntitySpawn::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    // a client only spawns its own deer, once, spit has to go through Fire
+                    let has_deer = clients[&address].player_id.is_some();
+                    if e.kind != EntityKind::Player || has_deer {
+                        socket.record_dropped();
+                        return;
                     }
+                    let finite = [e.pos.x, e.pos.y, e.scale, e.speed];
+                    if !finite.iter().all(|v| v.is_finite()) {
+                        socket.record_dropped();
+                        return;
+                    }
+                    // the spawn hook broadcasts whatever we clamp it to
+                    let scale = e.scale.clamp(0.0, MAX_CLIENT_SCALE);
+                    let speed = e.speed.clamp(0.0, MAX_CLIENT_SPEED);
+                    if (scale, speed) != (e.scale, e.speed) {
+                        println!(
+                            "server - clamped {:?} spawn from {} (scale {}, speed {})",
+                            e.kind, address, e.scale, e.speed
+                        );
+                        (e.scale, e.speed) = (scale, speed);
+                    }
+                    // the client spawns its deer before any trees reach it, so only we can
+                    // tell whether the spot is free
+                    let pos = ents.find_free_position(e.pos, PLAYER_SPAWN_SEARCH, e.scale);
+                    let id =
+                        ents.spawn(pos, e.scale, e.speed, 0.0, e.dir, SpriteName::None, e.kind);
 
-                    broadcast(e.into(), &socket, Some(address), clients.keys().copied());
+                    #[cfg(debug_assertions)]
+                    ents.get_mut(id).set_label(format!("deer of {}", address));
+                    clients.get_mut(&address).unwrap().player_id = Some(id);
+                    if (pos - e.pos).len2() > 0.0 {
+                        let moved = EntityUpdate {
+                            id: 0,
+                            pos,
+                            rotation: 0.0,
+                        };
+                        socket.send(moved, address).unwrap();
+                    }
                 }
                 OpCode::EntityUpdate => {
-                    let mut e = EntityUpdate::try_from(p).unwrap();
-                    if e.id == 0 {
-                        // player update
-                        // fetch the player id
-                        e.id = player_ids[&address];
+                    let Ok(mut e) = EntityUpdate::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    let conn = &clients[&address];
+                    let Some(player_id) = conn.player_id else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    // clients only move their own deer, which they call 0
+                    // and once a client sends input its deer moves where the server says
+                    if (e.id != 0 && e.id != player_id) || conn.input.is_some() {
+                        socket.record_dropped();
+                        return;
                     }
+                    e.id = player_id;
                     ents.set_position(e.id, e.pos);
+                    ents.set_rotation(e.id, e.rotation);
 
                     broadcast(e.into(), &socket, Some(address), clients.keys().copied());
                 }
                 OpCode::EntityDestroy => {
                     println!("server: entity destroy");
-                    let mut e = EntityDestroy::try_from(p).unwrap();
-                    if e.id == 0 {
-                        // player update
-                        // fetch the player id
-                        e.id = player_ids[&address];
+                    let Ok(e) = EntityDestroy::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    // only what the sender's deer shot, the deer itself goes when its
+                    // client leaves
+                    let player_id = clients[&address].player_id;
+                    let owned = player_id.is_some()
+                        && ents.contains(e.id)
+                        && ents.get(e.id).owner() == player_id;
+                    if !owned {
+                        socket.record_dropped();
+                        return;
                     }
                     ents.destroy(e.id);
-
-                    broadcast(e.into(), &socket, Some(address), clients.keys().copied());
                 }
+                OpCode::SpawnAck => {
+                    let Ok(e) = SpawnAck::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    acks.acked(address, e.id);
+                }
+                OpCode::Fire => {
+                    let Ok(e) = Fire::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    let conn = clients.get_mut(&address).unwrap();
+                    if let Some(player_id) = conn.player_id {
+                        let last_fire = &mut conn.last_fire;
+                        fire(socket, address, ents, player_id, e, last_fire, config);
+                    }
+                }
+                OpCode::Ready => {
+                    clients.get_mut(&address).unwrap().ready = true;
+                    // joined mid wave, nothing to wait for
+                    if wave_started {
+                        let start = Packet::new(OpCode::WaveStart, NoData);
+                        socket.send(start, address).unwrap();
+                    }
+                }
+                OpCode::Input => {
+                    let Ok(e) = Input::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    let conn = clients.get_mut(&address).unwrap();
+                    // late inputs are older than what the deer is already doing
+                    if conn.input.is_some_and(|last| e.seq <= last.seq) {
+                        socket.record_dropped();
+                        return;
+                    }
+                    // held until the next input, ents.tick moves the deer by speed and dt
+                    // spit still goes through Fire, the fire flag is for lockstep peers
+                    if let Some(player_id) = conn.player_id {
+                        ents.get_mut(player_id).set_direction(e.input.dir());
+                    }
+                    conn.input = Some(e);
+                }
+                OpCode::Identify => {
+                    let Ok(Identify { token }) = Identify::try_from(p) else {
+                        socket.record_dropped();
+                        return;
+                    };
+                    // only a client's first packet takes a deer back, this one is just
+                    // remembered for when it times out
+                    let conn = clients.get_mut(&address).unwrap();
+                    conn.token.get_or_insert(token);
+                }
+                OpCode::ListEntities => {
+                    if cfg!(debug_assertions) || config.admin == Some(address.ip()) {
+                        send_entity_list(ents, socket, address);
+                    }
+                }
+                // only ever sent by the server
+                OpCode::EntityList
+                | OpCode::Collision
+                | OpCode::GameOver
+                | OpCode::WorldReset
+                | OpCode::WaveStart
+                | OpCode::PowerupApplied
+                | OpCode::FireAck
+                | OpCode::Stun => (),
             }
         }
+
+        flush_events(events, socket, Some(address), clients, acks);
     }
 }
 
-fn tick(
+fn send_entity_list(ents: &entities::EntityManager, socket: &Server, address: SocketAddr) {
+    let entries = ents
+        .iter()
+        .map(|(id, e)| EntityListing {
+            id,
+            kind: e.kind(),
+            pos: e.pos(),
+        })
+        .collect::<Vec<_>>();
+
+    for chunk in entries.chunks(EntityList::MAX_ENTRIES) {
+        let packet = EntityList {
+            entries: chunk.to_vec(),
+        };
+        socket.send(packet, address).unwrap();
+    }
+}
+
+/// spawns a shot's spit for `player_id` unless it's firing faster than its cooldown allows
+fn fire(
+    socket: &Server,
+    address: SocketAddr,
+    ents: &mut entities::EntityManager,
+    player_id: i32,
+    request: Fire,
+    last_fire: &mut Option<Instant>,
+    config: &GameConfig,
+) {
+    let player = ents.get(player_id);
+    let rate = if player.has_effect(Effect::Powerup(Powerup::RapidFire)) {
+        RAPID_FIRE_RATE
+    } else {
+        1
+    };
+    let spread = player.has_effect(Effect::Powerup(Powerup::SpreadShot));
+    let stun = player.has_effect(Effect::Powerup(Powerup::StunShot));
+    let homing = player.has_effect(Effect::Powerup(Powerup::HomingShot));
+    let pos = player.pos();
+
+    // a little slack so jitter doesn't eat legitimate shots
+    let weapon = config.weapon;
+    let interval = weapon.cooldown * FIRE_SLACK_PERCENT / (100 * rate);
+    let now = Instant::now();
+    if last_fire.is_some_and(|t| now - t < interval) {
+        socket.record_dropped();
+        return;
+    }
+
+    let dirs = shot_directions(request.dir, spread);
+    let live = ents
+        .iter_kind(EntityKind::PlayerProjectile)
+        .filter(|(_, p)| p.owner() == Some(player_id))
+        .count();
+    if live + dirs.len() > weapon.max_live_projectiles {
+        socket.record_dropped();
+        return;
+    }
+    *last_fire = Some(now);
+
+    for (i, dir) in dirs.into_iter().enumerate() {
+        let kind = EntityKind::PlayerProjectile;
+        let id = ents.spawn(
+            pos,
+            weapon.projectile_scale,
+            weapon.projectile_speed,
+            0.0,
+            dir,
+            SpriteName::None,
+            kind,
+        );
+        let spit = ents.get_mut(id);
+        spit.set_ttl(weapon.projectile_ttl);
+        spit.set_owner(Some(player_id));
+        if stun {
+            let stun_shot = Effect::Powerup(Powerup::StunShot);
+            spit.add_effect(stun_shot, weapon.projectile_ttl);
+        }
+        if homing {
+            let homing_shot = Effect::Powerup(Powerup::HomingShot);
+            spit.add_effect(homing_shot, weapon.projectile_ttl);
+        }
+
+        let ack = FireAck {
+            correlation: request.correlation.wrapping_add(i as u16),
+            id,
+        };
+        socket.send(ack, address).unwrap();
+    }
+}
+
+fn kick_client(
+    address: SocketAddr,
+    socket: &Server,
+    ents: &mut entities::EntityManager,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
+) {
+    println!("kicking client: {}", address);
+    if let Err(e) = socket.kick(address) {
+        println!("server - failed to kick {}: {}", address, e);
+    }
+    drop_client(address, socket, ents, clients);
+}
+
+/// forgets a client and destroys its deer
+fn drop_client(
+    address: SocketAddr,
+    socket: &Server,
     ents: &mut entities::EntityManager,
-    clients: &mut HashMap<SocketAddr, Timer>,
-    player_ids: &mut HashMap<SocketAddr, i32>,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
+) {
+    let conn = clients.remove(&address);
+    socket.forget(address);
+    if let Some(id) = conn.and_then(|c| c.player_id) {
+        let orphans = destroy_deer(id, ents);
+        println!(
+            "Purging client [ent={}, {} orphans]- {}",
+            id, orphans, address
+        );
+    }
+}
+
+/// forgets a client, its deer stays put a while if it can come back for it
+fn detach_client(
+    address: SocketAddr,
     socket: &Server,
-    dt: Duration,
+    ents: &mut entities::EntityManager,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
+    detached: &mut Detached,
 ) {
-    let mut purge_list = Vec::new();
-    for (address, timer) in clients.iter_mut() {
-        if timer.tick(dt) {
-            purge_list.push(*address);
+    let Some((token, id)) = clients.get(&address).and_then(|c| c.token.zip(c.player_id)) else {
+        drop_client(address, socket, ents, clients);
+        return;
+    };
+    detached.insert(token, id);
+    clients.remove(&address);
+    socket.forget(address);
+    println!("Detaching client [ent={}]- {}", id, address);
+}
+
+/// destroys a deer and the spit it shot, returns how much spit that was
+fn destroy_deer(id: i32, ents: &mut entities::EntityManager) -> usize {
+    ents.destroy(id);
+    // the despawn hook tells everyone else about these too
+    ents.destroy_by_owner(id).len()
+}
+
+fn tick(
+    socket: &Server,
+    ents: &mut entities::EntityManager,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
+    detached: &mut Detached,
+    enemy: &EnemyConfig,
+    dt: Duration,
+) -> Option<GameOverReason> {
+    // before the timeouts, so a deer detached this tick gets its whole grace
+    for id in detached.expired(dt) {
+        if ents.contains(id) {
+            let orphans = destroy_deer(id, ents);
+            println!("Purging detached deer [ent={}, {} orphans]", id, orphans);
         }
     }
 
-    for address in purge_list {
-        println!("purging client: {}", address);
-        for (k, v) in player_ids.iter() {
-            println!("player_ids: [{}]=>[{}]", k, v);
+    // the deer's destroy is queued by the despawn hook and flushed to whoever is left
+    let timed_out = clients
+        .iter_mut()
+        .filter_map(|(&address, conn)| conn.liveness.tick(dt).then_some(address))
+        .collect::<Vec<_>>();
+    for address in timed_out {
+        println!("client timed out: {}", address);
+        detach_client(address, socket, ents, clients, detached);
+    }
+
+    // hunters keep heading for the middle, whatever pushed them off course, straying
+    // further from the straight line the less aggressive they are
+    let walls = ents
+        .iter_kind(EntityKind::Wall)
+        .map(|(_, w)| (w.pos(), w.radius()))
+        .collect::<Vec<_>>();
+    let mut rng = thread_rng();
+    let max_stray = (1.0 - enemy.aggression.clamp(0.0, 1.0)) * MAX_WANDER_ANGLE;
+    for (_, h) in ents.iter_mut_kind(EntityKind::Enemy) {
+        let stray = if max_stray > 0.0 {
+            rng.gen_range(-max_stray..=max_stray)
+        } else {
+            0.0
+        };
+        let dir = (Vec2::default() - h.pos()).rotate(stray);
+        h.set_direction(steer_around_walls(h.pos(), h.radius(), dir, &walls));
+    }
+
+    steer_homing(ents, dt.as_secs_f32());
+    ents.tick(dt.as_secs_f32());
+    push_out_of_walls(ents, &walls);
+
+    // clients stop blinking the hunters that are back on their feet
+    for (id, h) in ents.iter_mut_kind(EntityKind::Enemy) {
+        if h.take_expired_effects().contains(&Effect::Stun) {
+            let recovered = Stun {
+                id,
+                duration: Duration::ZERO,
+            };
+            broadcast(recovered.into(), socket, None, clients.keys().copied());
         }
-        clients.remove(&address);
-        if let Some(id) = player_ids.remove(&address) {
-            broadcast(
-                EntityDestroy { id }.into(),
-                &socket,
-                None,
-                clients.keys().copied(),
-            );
-            println!("Purging client [ent={}]- {}", id, address);
+    }
+
+    // hunters that made it to the middle are done, and so is the round
+    let mut breached = false;
+    ents.retain(|_, e| {
+        let arrived = e.kind() == EntityKind::Enemy && e.pos().len2() < 1.0;
+        breached |= arrived;
+        !arrived
+    });
+
+    breached.then_some(GameOverReason::BaseDestroyed)
+}
+
+/// turns a hunter heading `dir` sideways when the closest wall in its way is near enough,
+/// away from the side the wall is on
+fn steer_around_walls(pos: Vec2, radius: f32, dir: Vec2, walls: &[(Vec2, f32)]) -> Vec2 {
+    let ahead = dir.normalize();
+    let blocking = walls
+        .iter()
+        .filter_map(|&(wall, wall_radius)| {
+            let to = wall - pos;
+            let along = to.x * ahead.x + to.y * ahead.y;
+            // negative when the wall is left of the path
+            let across = to.x * ahead.y - to.y * ahead.x;
+            let reach = radius + wall_radius;
+            let in_way = along > 0.0 && along < WALL_LOOKAHEAD + reach && across.abs() < reach;
+            in_way.then_some((along, across))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+
+    match blocking {
+        Some((_, across)) if across < 0.0 => ahead.rotate(90.0),
+        Some(_) => ahead.rotate(-90.0),
+        None => dir,
+    }
+}
+
+/// puts hunters that walked into a wall back against its edge
+fn push_out_of_walls(ents: &mut entities::EntityManager, walls: &[(Vec2, f32)]) {
+    for (_, h) in ents.iter_mut_kind(EntityKind::Enemy) {
+        for &(wall, wall_radius) in walls {
+            let offset = h.pos() - wall;
+            let reach = h.radius() + wall_radius;
+            let dist2 = offset.len2();
+            if dist2 < reach * reach && dist2 > f32::EPSILON {
+                h.set_pos(wall + reach * offset.normalize());
+            }
         }
     }
+}
 
-    ents.tick(dt.as_secs_f32());
+/// turns homing spit toward the nearest hunter in front of it
+/// clients fly spit straight, so homing spit goes out with the position updates too
+fn steer_homing(ents: &mut entities::EntityManager, dt: f32) {
+    let homing_shot = Effect::Powerup(Powerup::HomingShot);
+    let turns = ents
+        .iter_kind(EntityKind::PlayerProjectile)
+        .filter(|(_, p)| p.has_effect(homing_shot))
+        .filter_map(|(id, p)| {
+            let dir = p.dir();
+            // lowest id breaks ties so every run picks the same hunter
+            let (_, target) = ents
+                .iter_kind(EntityKind::Enemy)
+                .map(|(hid, h)| (hid, h.pos() - p.pos()))
+                .filter(|(_, to)| dir.angle_to(*to).abs() <= HOMING_CONE)
+                .min_by(|a, b| a.1.len2().total_cmp(&b.1.len2()).then(a.0.cmp(&b.0)))?;
+            let max_turn = HOMING_TURN_RATE * dt;
+            let turn = dir.angle_to(target).clamp(-max_turn, max_turn);
+            Some((id, dir.rotate(turn)))
+        })
+        .collect::<Vec<_>>();
+
+    for (id, dir) in turns {
+        let spit = ents.get_mut(id);
+        spit.set_direction(dir);
+        spit.set_rotation(dir.angle());
+    }
+}
+
+/// clears out the last round's hunters, spit and pickups, players and the forest stay
+fn reset_world(ents: &mut entities::EntityManager) {
+    ents.retain(|_, e| {
+        !matches!(
+            e.kind(),
+            EntityKind::Enemy | EntityKind::PlayerProjectile | EntityKind::Pickup
+        )
+    });
+}
+
+/// how far along the segment from `a` to `b` the point closest to `point` is, from 0 to 1,
+/// and the point itself
+fn closest_on_segment(a: Vec2, b: Vec2, point: Vec2) -> (f32, Vec2) {
+    let path = b - a;
+    let len2 = path.len2();
+    if len2 <= f32::EPSILON {
+        return (0.0, a);
+    }
+
+    let to = point - a;
+    let along = ((to.x * path.x + to.y * path.y) / len2).clamp(0.0, 1.0);
+    (along, a + along * path)
+}
+
+/// spit always hits hunters, and other players' deer only with friendly fire on
+/// spit is owned by the deer that shot it, deer themselves have no owner
+fn can_hit(spit: &dyn Entity, target_id: i32, target: &dyn Entity, friendly_fire: bool) -> bool {
+    match target.kind() {
+        EntityKind::Enemy => true,
+        EntityKind::Player => friendly_fire && spit.owner() != Some(target_id),
+        _ => false,
+    }
+}
 
-    let mut hunter_purge_list = Vec::new();
-    for (id, h) in ents.iter().filter(|e| e.1.kind() == EntityKind::Enemy) {
-        let d = h.pos().len2();
-        if d < 1.0 {
-            hunter_purge_list.push(id);
+/// destroys spit and the hunters it hits, telling clients where each hit landed
+/// returns where the hunters died
+/// spit is swept along the path it took this tick, so fast spit can't skip over a hunter
+/// between two ticks
+fn collide(
+    ents: &mut entities::EntityManager,
+    socket: &Server,
+    clients: &HashMap<SocketAddr, ClientConn>,
+    friendly_fire: bool,
+) -> Vec<Vec2> {
+    let mut hit = HashSet::new();
+    let mut collisions = Vec::new();
+    for (pid, p) in ents.iter_kind(EntityKind::PlayerProjectile) {
+        let from = p.prev_pos();
+        // the first target along the path, not just any that's touching
+        let target = ents
+            .iter()
+            .filter(|(tid, t)| can_hit(p, *tid, *t, friendly_fire) && !hit.contains(tid))
+            .filter_map(|(tid, t)| {
+                let (along, closest) = closest_on_segment(from, p.pos(), t.pos());
+                let reach = p.radius() + t.radius();
+                ((closest - t.pos()).len2() <= reach * reach).then_some((tid, along, closest))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((tid, _, point)) = target {
+            hit.insert(tid);
+            collisions.push(Collision {
+                a: pid,
+                b: tid,
+                point,
+            });
         }
     }
 
-    for id in hunter_purge_list {
-        ents.destroy(id);
-        broadcast(
-            EntityDestroy { id }.into(),
-            socket,
-            None,
-            clients.keys().copied(),
-        );
+    let mut kills = Vec::new();
+    for collision in collisions {
+        broadcast(collision.into(), socket, None, clients.keys().copied());
+        let stuns = ents
+            .get(collision.a)
+            .has_effect(Effect::Powerup(Powerup::StunShot));
+        ents.destroy(collision.a);
+        // deer have no health yet, they only get splattered
+        if ents.get(collision.b).kind() != EntityKind::Enemy {
+            continue;
+        }
+        if stuns {
+            let duration = STUN_DURATION;
+            ents.get_mut(collision.b).add_effect(Effect::Stun, duration);
+            let stun = Stun {
+                id: collision.b,
+                duration,
+            };
+            broadcast(stun.into(), socket, None, clients.keys().copied());
+        } else {
+            kills.push(ents.get(collision.b).pos());
+            ents.destroy(collision.b);
+        }
     }
+
+    kills
 }
 
-fn recv_loop(socket: Arc<Server>, tx: Sender<(Packet, SocketAddr)>) {
-    loop {
-        match socket.recv() {
-            Ok(msg) => {
-                // println!("server-get");
-                tx.send(msg).unwrap();
+/// pushes overlapping deer apart along the line between them, half the overlap each
+/// returns the deer that moved
+fn separate_players(ents: &mut entities::EntityManager) -> Vec<i32> {
+    let players = ents
+        .iter_kind(EntityKind::Player)
+        .map(|(id, e)| (id, e.pos(), e.radius()))
+        .collect::<Vec<_>>();
+
+    let mut pushes: HashMap<i32, Vec2> = HashMap::new();
+    for (i, &(a, pos_a, radius_a)) in players.iter().enumerate() {
+        for &(b, pos_b, radius_b) in &players[i + 1..] {
+            let reach = radius_a + radius_b;
+            let offset = pos_a - pos_b;
+            let dist2 = offset.len2();
+            if dist2 >= reach * reach {
+                continue;
             }
-            Err(Error::IoError(e)) if e.kind() == ErrorKind::ConnectionReset => (),
-            Err(e) => panic!("server - recv_loop error: {:?}", e),
+
+            // stacked exactly on top of each other, any way out will do
+            let normal = if dist2 > f32::EPSILON {
+                offset.normalize()
+            } else {
+                Vec2::new(1.0, 0.0)
+            };
+            let push = (0.5 * (reach - dist2.sqrt())) * normal;
+            let push_a = pushes.entry(a).or_default();
+            *push_a = *push_a + push;
+            let push_b = pushes.entry(b).or_default();
+            *push_b = *push_b - push;
         }
     }
+
+    for (&id, &push) in pushes.iter() {
+        let pos = ents.get(id).pos();
+        ents.set_position(id, pos + push);
+    }
+    pushes.into_keys().collect()
+}
+
+/// tells everyone where a deer is, its owner knows it as id 0
+fn send_player_position(
+    ents: &entities::EntityManager,
+    id: i32,
+    socket: &Server,
+    clients: &HashMap<SocketAddr, ClientConn>,
+) {
+    let e = ents.get(id);
+    for (&address, conn) in clients.iter() {
+        let update = EntityUpdate {
+            id: if conn.player_id == Some(id) { 0 } else { id },
+            pos: e.pos(),
+            rotation: e.rotation(),
+        };
+        socket.send(update, address).unwrap();
+    }
 }
 
-fn make_forest(ents: &mut entities::EntityManager) {
-    let num_trees = 5;
+/// some of the dead hunters leave a random powerup behind
+fn drop_pickups(
+    ents: &mut entities::EntityManager,
+    pickups: &mut HashMap<i32, Powerup>,
+    kills: Vec<Vec2>,
+    chance: f64,
+) {
     let mut rng = thread_rng();
+    for pos in kills {
+        if !rng.gen_bool(chance) {
+            continue;
+        }
 
-    for _ in 0..num_trees {
-        let spread = 12.0;
-        let x = rng.gen_range((-spread)..=spread);
-        let y = rng.gen_range((-spread)..=spread);
-        ents.spawn(
-            Vec2::new(x, y),
-            8.0,
+        let powerup = Powerup::ALL[rng.gen_range(0..Powerup::ALL.len())];
+        let kind = EntityKind::Pickup;
+        let scale = 3.0;
+        let pos = ents.find_free_position(pos, PICKUP_SEARCH, scale);
+        let id = ents.spawn(
+            pos,
+            scale,
             0.0,
             0.0,
             Vec2::default(),
             SpriteName::None,
-            EntityKind::Forest,
+            kind,
         );
+        ents.get_mut(id).set_ttl(PICKUP_TTL);
+        pickups.insert(id, powerup);
     }
 }
 
-fn spawn_hunter(
+/// hands pickups to the first deer touching them and tells its client
+fn collect_pickups(
     ents: &mut entities::EntityManager,
+    pickups: &mut HashMap<i32, Powerup>,
     socket: &Server,
-    clients: impl Iterator<Item = SocketAddr>,
+    clients: &HashMap<SocketAddr, ClientConn>,
+    duration: Duration,
 ) {
-    let mut rng = thread_rng();
-    let bound = WORLD_SIZE as f32;
-    let x = rng.gen_range(-bound..bound);
-    let y = rng.gen_range(-bound..bound);
-    let pos = Vec2::new(x, y);
-    let scale = 5.25;
-    let speed = 24.0;
-    let dir = Vec2::default() - pos;
-    let rotation = dir.angle();
-    let kind = EntityKind::Enemy;
-
-    let id = ents.spawn(pos, scale, speed, rotation, dir, SpriteName::None, kind);
-    let packet = EntitySpawn {
-        id,
-        kind,
-        pos,
-        scale,
-        speed,
-        dir,
-    };
+    pickups.retain(|&id, _| ents.contains(id));
 
-    broadcast(packet.into(), socket, None, clients);
-}
+    let mut collected = Vec::new();
+    for (&pid, &powerup) in pickups.iter() {
+        let pickup = ents.get(pid);
+        let player = ents.iter_kind(EntityKind::Player).find(|(_, p)| {
+            let reach = pickup.radius() + p.radius();
+            (pickup.pos() - p.pos()).len2() <= reach * reach
+        });
+        if let Some((player_id, _)) = player {
+            collected.push((pid, player_id, powerup));
+        }
+    }
 
-pub fn run(port: u16) {
-    let mut ents = entities::EntityManager::default();
-    let mut player_ids = HashMap::new();
-    let mut clients = HashMap::new();
-    let socket = Arc::new(Server::listen(port).unwrap());
-    let send_socket = socket.clone();
+    for (pid, player_id, powerup) in collected {
+        pickups.remove(&pid);
+        ents.destroy(pid);
+        ents.get_mut(player_id)
+            .add_effect(Effect::Powerup(powerup), duration);
 
-    let (tx, rx) = mpsc::channel();
+        let owner = clients.iter().find(|(_, c)| c.player_id == Some(player_id));
+        if let Some((&address, _)) = owner {
+            let applied = PowerupApplied { powerup, duration };
+            socket.send(applied, address).unwrap();
+        }
+    }
+}
 
-    make_forest(&mut ents);
+/// whether clients need position updates for `e` to see where it went, input driven deer
+/// aside
+fn moved_by_server(e: &dyn Entity) -> bool {
+    let homing = e.kind() == EntityKind::PlayerProjectile
+        && e.has_effect(Effect::Powerup(Powerup::HomingShot));
+    e.kind().server_owned() || homing
+}
 
-    thread::spawn(move || recv_loop(send_socket, tx));
+/// sends positions of server steered and input driven entities that moved past the dead zone
+/// returns the entities that came back into a client's range, they're worth a fresh spawn
+fn broadcast_moved(
+    ents: &entities::EntityManager,
+    driven: &HashSet<i32>,
+    last_sent: &mut HashMap<i32, Vec2>,
+    dead_zone: f32,
+    interest_radius: Option<f32>,
+    socket: &Server,
+    clients: &mut HashMap<SocketAddr, ClientConn>,
+) -> Vec<(SocketAddr, i32)> {
+    let mut live = HashSet::new();
+    let mut returned = Vec::new();
+    // one buffer for every update this tick
+    let mut buf = Vec::with_capacity(1 + EntityUpdate::SIZE);
+    let moved_here = ents
+        .iter()
+        .filter(|e| moved_by_server(e.1) || driven.contains(&e.0));
+    for (id, e) in moved_here {
+        live.insert(id);
+        let pos = e.pos();
+        let moved = last_sent
+            .get(&id)
+            .map_or(true, |&last| (pos - last).len2() > dead_zone * dead_zone);
+        if moved {
+            last_sent.insert(id, pos);
+        }
+
+        let update = EntityUpdate {
+            id,
+            pos,
+            rotation: e.rotation(),
+        };
+        buf.clear();
+        update.write_into(&mut buf);
+        for (&address, conn) in clients.iter_mut() {
+            // clients without a deer yet watch the whole world
+            let near = match (interest_radius, conn.player_id) {
+                (Some(radius), Some(player)) => {
+                    (ents.get(player).pos() - pos).len2() <= radius * radius
+                }
+                _ => true,
+            };
+            if !near {
+                if conn.in_range.remove(&id) {
+                    conn.culled.insert(id);
+                }
+                continue;
+            }
+            if conn.culled.remove(&id) {
+                returned.push((address, id));
+            }
+
+            // whatever the client last heard is stale once the entity comes back into range
+            let entered = conn.in_range.insert(id);
+            if !(moved || entered) {
+                continue;
+            }
+            if conn.player_id == Some(id) {
+                socket
+                    .send(EntityUpdate { id: 0, ..update }, address)
+                    .unwrap();
+            } else {
+                socket.send_bytes(&buf, address).unwrap();
+            }
+        }
+    }
+
+    last_sent.retain(|id, _| live.contains(id));
+    for conn in clients.values_mut() {
+        conn.in_range.retain(|id| live.contains(id));
+        conn.culled.retain(|id| live.contains(id));
+    }
+    returned
+}
+
+/// the authoritative side of the desync log
+fn log_positions(ents: &entities::EntityManager, seq: u32, time: Duration) {
+    for (id, e) in ents.iter() {
+        if !e.kind().is_static() {
+            log_position(seq, Some(time), id, e.kind(), e.pos());
+        }
+    }
+}
+
+fn recv_loop(socket: Arc<Server>, tx: Sender<(Packet, SocketAddr)>) {
+    loop {
+        match socket.recv() {
+            Ok(msg) => {
+                // println!("server-get");
+                tx.send(msg).unwrap();
+            }
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::ConnectionReset => {
+                socket.record_dropped()
+            }
+            // nobody we'd talk to sends these, don't let them take the server down
+            Err(Error::PacketTooLarge) => socket.record_dropped(),
+            Err(e) => panic!("server - recv_loop error: {:?}", e),
+        }
+    }
+}
+
+/// whether the wave is on, it starts once every client is ready and stops when the last
+/// one leaves, so whoever left no longer holds up the rest
+fn wave_running(clients: &HashMap<SocketAddr, ClientConn>, wave_started: bool) -> bool {
+    if clients.is_empty() {
+        return false;
+    }
+    wave_started || clients.values().all(|c| c.ready)
+}
+
+/// spawns the hunters that came due during `dt` while the wave is on, up to the cap
+/// returns how many were spawned
+fn spawn_due_hunters(
+    ents: &mut entities::EntityManager,
+    hunter_timer: &mut Interval,
+    dt: Duration,
+    wave_on: bool,
+    config: &GameConfig,
+) -> usize {
+    // nobody is around to fight them, so don't let hunters pile up
+    if !wave_on {
+        return 0;
+    }
+    // a hitch shouldn't thin out the wave
+    let due = hunter_timer.tick(dt) as usize;
+    spawn_hunters(ents, due, config)
+}
+
+/// spawns up to `n` hunters, as many as fit under the cap, returns how many were spawned
+fn spawn_hunters(ents: &mut entities::EntityManager, n: usize, config: &GameConfig) -> usize {
+    let enemies = ents.count_of_kind(EntityKind::Enemy);
+    let spawned = n.min(config.max_enemies.saturating_sub(enemies));
+    let mut rng = thread_rng();
+    for _ in 0..spawned {
+        spawn_hunter(ents, &config.enemy, &mut rng);
+    }
+    spawned
+}
+
+/// anywhere in the world, facing the middle
+fn spawn_hunter(
+    ents: &mut entities::EntityManager,
+    enemy: &EnemyConfig,
+    rng: &mut impl Rng,
+) -> i32 {
+    let bound = WORLD_SIZE as f32;
+    let x = rng.gen_range(-bound..bound);
+    let y = rng.gen_range(-bound..bound);
+    let pos = Vec2::new(x, y);
+    let scale = enemy.scale;
+    let speed = enemy.speed;
+    let dir = Vec2::default() - pos;
+    let rotation = dir.angle();
+    let kind = EntityKind::Enemy;
+
+    ents.spawn(pos, scale, speed, rotation, dir, SpriteName::None, kind)
+}
+
+/// `console` reads operator commands from stdin, only a dedicated server should own stdin
+pub fn run(port: u16, config: GameConfig, console: bool) {
+    let mut ents = entities::EntityManager::default();
+    ents.set_max_entities_warn(config.max_entities_warn);
+    let mut clients = HashMap::new();
+    let mut acks = SpawnAcks::default();
+    let mut detached = Detached::default();
+    let socket = Arc::new(Server::listen(port).unwrap());
+    let send_socket = socket.clone();
+
+    let (tx, rx) = mpsc::channel();
+    let (event_tx, event_rx) = mpsc::channel();
+
+    let map = config.map.map(|path| {
+        Map::load(Path::new(path)).unwrap_or_else(|e| panic!("can't load map {}: {}", path, e))
+    });
+
+    // clients lay out the tiles themselves from this, and the trees too with local_statics
+    // a map replaces the trees, so there's nothing for them to plant
+    let world = WorldInfo {
+        seed: thread_rng().gen(),
+        size: WORLD_SIZE,
+        local_statics: config.local_statics && map.is_none(),
+    };
+    socket.set_hello_data(world.to_bytes());
+    match &map {
+        Some(map) => {
+            for &pos in map.walls() {
+                let kind = EntityKind::Wall;
+                let dir = Vec2::default();
+                ents.spawn(pos, MAP_CELL / 2.0, 0.0, 0.0, dir, SpriteName::None, kind);
+            }
+        }
+        None => ents.plant_trees(world, SpriteName::None),
+    }
+    register_event_hooks(&mut ents, event_tx);
+
+    thread::spawn(move || recv_loop(send_socket, tx));
+
+    let (command_tx, command_rx) = mpsc::channel();
+    if console {
+        thread::spawn(move || console::console_loop(command_tx));
+    }
 
     let mut last = Instant::now();
-    let mut ping_timer = Timer::new(Duration::from_secs(1));
-    let mut hunter_timer = Timer::new(Duration::from_millis(500));
+    // the floor rises with the ping interval, but never past the longest timeout
+    config.check_ping_interval(MAX_TIMEOUT);
+    let mut ping_timer = Interval::new(config.ping_interval);
+    let mut hunter_timer = Interval::new(Duration::from_millis(500));
+    let mut stats_timer = Timer::new(STATS_INTERVAL);
+    let mut position_log = config.position_log_interval.map(Interval::new);
+    let mut position_seq = 0;
+    // running while the lost round is shown to players
+    let mut round_reset: Option<Timer> = None;
+    let mut pickups = HashMap::new();
+    let mut wave_started = false;
+    let mut update_timer = Timer::new(config.entity_update_interval);
+    let mut last_sent = HashMap::new();
     loop {
-        read_packet_and_update_world(&socket, &rx, &mut clients, &mut ents, &mut player_ids);
+        if let Ok(cmd) = command_rx.try_recv() {
+            match cmd {
+                Command::Clients => {
+                    println!("{} client(s) connected", socket.client_count());
+                    for address in socket.client_addresses() {
+                        println!("  {}", address);
+                    }
+                }
+                Command::Kick(address) => kick_client(address, &socket, &mut ents, &mut clients),
+                Command::Ban(ip) => {
+                    socket.ban(ip);
+                    let banned = clients
+                        .keys()
+                        .filter(|a| a.ip() == ip)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    for address in banned {
+                        kick_client(address, &socket, &mut ents, &mut clients);
+                    }
+                }
+                Command::SpawnWave(n) => {
+                    let spawned = spawn_hunters(&mut ents, n, &config);
+                    if spawned < n {
+                        println!(
+                            "spawned {} of {} hunters, the cap is {}",
+                            spawned, n, config.max_enemies
+                        );
+                    }
+                }
+                Command::Dump => print!("{}", ents.debug_dump()),
+                Command::Quit => {
+                    println!("server shutting down");
+                    break;
+                }
+            }
+        }
+
+        read_packet_and_update_world(
+            &socket,
+            &rx,
+            &event_rx,
+            &mut clients,
+            &mut ents,
+            &mut acks,
+            &mut detached,
+            wave_started,
+            &config,
+        );
 
         let now = Instant::now();
         let dt = now - last;
-        tick(&mut ents, &mut clients, &mut player_ids, &socket, dt);
+        let lost = tick(
+            &socket,
+            &mut ents,
+            &mut clients,
+            &mut detached,
+            &config.enemy,
+            dt,
+        );
         last = now;
+        let kills = collide(&mut ents, &socket, &clients, config.friendly_fire);
+        if config.player_collision {
+            for id in separate_players(&mut ents) {
+                send_player_position(&ents, id, &socket, &clients);
+            }
+        }
+        drop_pickups(&mut ents, &mut pickups, kills, config.pickup_chance);
+        collect_pickups(
+            &mut ents,
+            &mut pickups,
+            &socket,
+            &clients,
+            config.powerup_duration,
+        );
+
+        if let Some(reason) = lost.filter(|_| round_reset.is_none()) {
+            println!("game over - {:?}", reason);
+            broadcast(
+                GameOver { reason }.into(),
+                &socket,
+                None,
+                clients.keys().copied(),
+            );
+            round_reset = Some(Timer::new(ROUND_RESET_DELAY));
+        } else if round_reset.as_mut().is_some_and(|t| t.tick(dt)) {
+            println!("starting a new round");
+            reset_world(&mut ents);
+            let reset = Packet::new(OpCode::WorldReset, NoData);
+            broadcast(reset, &socket, None, clients.keys().copied());
+            round_reset = None;
+            wave_started = false;
+            for conn in clients.values_mut() {
+                conn.ready = false;
+            }
+        }
+
+        let running = wave_running(&clients, wave_started);
+        if running && !wave_started {
+            println!("everyone's ready, starting the wave");
+            let start = Packet::new(OpCode::WaveStart, NoData);
+            broadcast(start, &socket, None, clients.keys().copied());
+        }
+        wave_started = running;
 
-        if ping_timer.tick(dt) {
+        // one ping covers any that were missed
+        if ping_timer.tick(dt) > 0 {
             let ping = Packet::new(socket::OpCode::Ping, NoData);
             // println!("server - ping");
             broadcast(ping, &socket, None, clients.keys().copied());
+            clients.values_mut().for_each(|c| c.liveness.pinged());
+        }
+        let wave_on = wave_started && round_reset.is_none();
+        spawn_due_hunters(&mut ents, &mut hunter_timer, dt, wave_on, &config);
+        flush_events(&event_rx, &socket, None, &clients, &mut acks);
+        if update_timer.tick(dt) {
+            let driven = clients
+                .values()
+                .filter(|c| c.input.is_some())
+                .filter_map(|c| c.player_id)
+                .collect();
+            let returned = broadcast_moved(
+                &ents,
+                &driven,
+                &mut last_sent,
+                config.update_dead_zone,
+                config.interest_radius,
+                &socket,
+                &mut clients,
+            );
+            // the update just sent is enough for clients that kept the entity, the spawn is
+            // for ones that lost it while it was out of range
+            for (address, id) in returned {
+                acks.send(entity_spawn(id, ents.get(id)), address, &socket);
+            }
+        }
+        acks.tick(dt, &socket, &clients, &ents);
+        if position_log.as_mut().is_some_and(|t| t.tick(dt) > 0) {
+            log_positions(&ents, position_seq, socket.uptime());
+            position_seq += 1;
+        }
+        if config.log_stats && stats_timer.tick(dt) {
+            let stats = ServerStats::collect(&socket, &ents, STATS_INTERVAL);
+            println!("server stats - {}", stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::common::PlayerInput;
+
+    fn spawn_kind(ents: &mut entities::EntityManager, kind: EntityKind) -> i32 {
+        let zero = Vec2::default();
+        ents.spawn(zero, 1.0, 0.0, 0.0, zero, SpriteName::None, kind)
+    }
+
+    /// a listening server fed packets directly, as if they came off the wire
+    struct Harness<'e> {
+        socket: Server,
+        tx: Sender<(Packet, SocketAddr)>,
+        rx: Receiver<(Packet, SocketAddr)>,
+        events: Receiver<Packet>,
+        clients: HashMap<SocketAddr, ClientConn>,
+        ents: entities::EntityManager<'e, 'e>,
+        acks: SpawnAcks,
+        detached: Detached,
+        config: GameConfig,
+    }
+
+    impl<'e> Harness<'e> {
+        fn new() -> Self {
+            let (tx, rx) = mpsc::channel();
+            let (events_tx, events) = mpsc::channel();
+            let mut ents = entities::EntityManager::default();
+            register_event_hooks(&mut ents, events_tx);
+            Self {
+                socket: Server::listen(0).unwrap(),
+                tx,
+                rx,
+                events,
+                clients: HashMap::new(),
+                ents,
+                acks: SpawnAcks::default(),
+                detached: Detached::default(),
+                config: GameConfig::default(),
+            }
+        }
+
+        fn deliver(&mut self, packet: impl Into<Packet>, from: SocketAddr) {
+            self.tx.send((packet.into(), from)).unwrap();
+            read_packet_and_update_world(
+                &self.socket,
+                &self.rx,
+                &self.events,
+                &mut self.clients,
+                &mut self.ents,
+                &mut self.acks,
+                &mut self.detached,
+                false,
+                &self.config,
+            );
+        }
+
+        /// the new client's deer
+        fn join(&mut self, from: SocketAddr) -> i32 {
+            self.deliver(spawn_packet(EntityKind::Player, Vec2::default()), from);
+            self.clients[&from].player_id.unwrap()
         }
-        if hunter_timer.tick(dt) {
-            spawn_hunter(&mut ents, &socket, clients.keys().copied());
+    }
+
+    fn spawn_packet(kind: EntityKind, pos: Vec2) -> EntitySpawn {
+        EntitySpawn {
+            id: 0,
+            kind,
+            pos,
+            scale: 4.0,
+            speed: 12.0,
+            dir: Vec2::default(),
         }
     }
+
+    fn address(port: u16) -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, port).into()
+    }
+
+    #[test]
+    fn friendly_fire_spares_the_shooter() {
+        let mut ents = entities::EntityManager::default();
+        let shooter = spawn_kind(&mut ents, EntityKind::Player);
+        let other = spawn_kind(&mut ents, EntityKind::Player);
+        let hunter = spawn_kind(&mut ents, EntityKind::Enemy);
+        let spit = spawn_kind(&mut ents, EntityKind::PlayerProjectile);
+        ents.get_mut(spit).set_owner(Some(shooter));
+
+        let hits = |id, ff| can_hit(ents.get(spit), id, ents.get(id), ff);
+        assert!(!hits(shooter, true));
+        assert!(hits(other, true));
+        assert!(!hits(other, false));
+        assert!(hits(hunter, false));
+    }
+
+    #[test]
+    fn homing_spit_converges_on_a_hunter_and_is_broadcast() {
+        let mut ents = entities::EntityManager::default();
+        let hunter = spawn_kind(&mut ents, EntityKind::Enemy);
+        ents.set_position(hunter, Vec2::new(20.0, 10.0));
+        let spit = ents.spawn(
+            Vec2::default(),
+            1.0,
+            10.0,
+            0.0,
+            Vec2::new(1.0, 0.0),
+            SpriteName::None,
+            EntityKind::PlayerProjectile,
+        );
+        assert!(!moved_by_server(ents.get(spit)));
+        let homing_shot = Effect::Powerup(Powerup::HomingShot);
+        ents.get_mut(spit)
+            .add_effect(homing_shot, Duration::from_secs(10));
+        assert!(moved_by_server(ents.get(spit)));
+
+        let dt = 1.0 / 60.0;
+        let start = ents.get(spit).dir().angle_to(Vec2::new(20.0, 10.0)).abs();
+        for _ in 0..30 {
+            steer_homing(&mut ents, dt);
+            ents.tick(dt);
+        }
+        let s = ents.get(spit);
+        let off = s.dir().angle_to(Vec2::new(20.0, 10.0) - s.pos()).abs();
+        assert!(off < start / 2.0, "still {} degrees off", off);
+        assert!((s.rotation() - s.dir().angle()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn clients_only_spawn_one_deer() {
+        let mut h = Harness::new();
+        let client = address(40001);
+        h.join(client);
+        h.deliver(spawn_packet(EntityKind::Player, Vec2::default()), client);
+        h.deliver(spawn_packet(EntityKind::Enemy, Vec2::default()), client);
+        h.deliver(spawn_packet(EntityKind::Wall, Vec2::default()), client);
+
+        assert_eq!(h.ents.count_of_kind(EntityKind::Player), 1);
+        assert_eq!(h.ents.count(), 1);
+        assert_eq!(h.socket.take_traffic().dropped, 3);
+    }
+
+    #[test]
+    fn unknown_opcodes_and_kinds_are_dropped() {
+        let mut h = Harness::new();
+        let mut data = Packet::from(spawn_packet(EntityKind::Player, Vec2::default())).data();
+        data[4] = 200;
+        h.deliver(Packet::new(OpCode::EntitySpawn, data), address(40001));
+
+        assert_eq!(h.ents.count(), 0);
+        assert_eq!(h.socket.take_traffic().dropped, 1);
+
+        h.deliver(Packet::new(u8::MAX, [0; 8]), address(40001));
+        assert_eq!(h.socket.take_traffic().dropped, 1);
+    }
+
+    #[test]
+    fn deer_spawned_in_a_tree_are_moved_out_of_it() {
+        let mut h = Harness::new();
+        let zero = Vec2::default();
+        let tree = h.ents.spawn(
+            zero,
+            8.0,
+            0.0,
+            0.0,
+            zero,
+            SpriteName::Forest,
+            EntityKind::Forest,
+        );
+        let deer = h.join(address(40001));
+
+        let (tree, deer) = (h.ents.get(tree), h.ents.get(deer));
+        let reach = tree.radius() + deer.radius();
+        assert!((deer.pos() - tree.pos()).len2() >= reach * reach);
+    }
+
+    #[test]
+    fn clients_only_move_their_own_deer() {
+        let mut h = Harness::new();
+        let (a, b) = (address(40001), address(40002));
+        h.join(a);
+        let deer_b = h.join(b);
+        let start = h.ents.get(deer_b).pos();
+        let moved = Vec2::new(50.0, 50.0);
+        h.deliver(
+            EntityUpdate {
+                id: deer_b,
+                pos: moved,
+                rotation: 0.0,
+            },
+            a,
+        );
+        assert_eq!(h.ents.get(deer_b).pos().x, start.x);
+
+        h.deliver(
+            EntityUpdate {
+                id: 0,
+                pos: moved,
+                rotation: 0.0,
+            },
+            b,
+        );
+        assert_eq!(h.ents.get(deer_b).pos().x, 50.0);
+    }
+
+    #[test]
+    fn clients_only_destroy_their_own_spit() {
+        let mut h = Harness::new();
+        let (a, b) = (address(40001), address(40002));
+        let deer_a = h.join(a);
+        let deer_b = h.join(b);
+        let hunter = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        let spit = spawn_kind(&mut h.ents, EntityKind::PlayerProjectile);
+        h.ents.get_mut(spit).set_owner(Some(deer_a));
+
+        for id in [0, deer_a, deer_b, hunter, spit, 1234] {
+            h.deliver(EntityDestroy { id }, b);
+        }
+        assert!([deer_a, deer_b, hunter, spit]
+            .iter()
+            .all(|&id| h.ents.contains(id)));
+
+        h.deliver(EntityDestroy { id: spit }, a);
+        assert!(!h.ents.contains(spit));
+    }
+
+    #[test]
+    fn hunters_only_come_while_someone_is_there_and_ready() {
+        let mut clients = HashMap::new();
+        assert!(!wave_running(&clients, true));
+
+        clients.insert(address(40001), ClientConn::new(&GameConfig::default()));
+        clients.insert(address(40002), ClientConn::new(&GameConfig::default()));
+        assert!(!wave_running(&clients, false));
+        clients.get_mut(&address(40001)).unwrap().ready = true;
+        assert!(!wave_running(&clients, false));
+        clients.get_mut(&address(40002)).unwrap().ready = true;
+        assert!(wave_running(&clients, false));
+
+        clients.clear();
+        let mut ents = entities::EntityManager::default();
+        let mut timer = Interval::new(Duration::from_millis(500));
+        let config = GameConfig::default();
+        let wave_on = wave_running(&clients, true);
+        let spawned = spawn_due_hunters(
+            &mut ents,
+            &mut timer,
+            Duration::from_secs(5),
+            wave_on,
+            &config,
+        );
+        assert_eq!(spawned, 0);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 0);
+    }
+
+    #[test]
+    fn hunter_spawns_stop_at_the_cap() {
+        let mut ents = entities::EntityManager::default();
+        let mut timer = Interval::new(Duration::from_millis(500));
+        let config = GameConfig {
+            max_enemies: 3,
+            ..GameConfig::default()
+        };
+        // four are due, only three fit
+        let spawned =
+            spawn_due_hunters(&mut ents, &mut timer, Duration::from_secs(2), true, &config);
+        assert_eq!(spawned, 3);
+
+        let hunter = ents.iter_kind(EntityKind::Enemy).next().unwrap().0;
+        ents.destroy(hunter);
+        let spawned =
+            spawn_due_hunters(&mut ents, &mut timer, Duration::from_secs(2), true, &config);
+        assert_eq!(spawned, 1);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 3);
+    }
+
+    #[test]
+    fn a_console_wave_only_fills_the_room_under_the_cap() {
+        let mut ents = entities::EntityManager::default();
+        let config = GameConfig {
+            max_enemies: 3,
+            ..GameConfig::default()
+        };
+        spawn_kind(&mut ents, EntityKind::Enemy);
+        assert_eq!(spawn_hunters(&mut ents, 10, &config), 2);
+        assert_eq!(spawn_hunters(&mut ents, 10, &config), 0);
+        assert_eq!(ents.count_of_kind(EntityKind::Enemy), 3);
+    }
+
+    #[test]
+    fn list_entities_round_trips_over_loopback() {
+        let mut harness = Harness::new();
+        let ids = (0..EntityList::MAX_ENTRIES + 5)
+            .map(|i| {
+                let kind = if i % 2 == 0 {
+                    EntityKind::Enemy
+                } else {
+                    EntityKind::Forest
+                };
+                let id = spawn_kind(&mut harness.ents, kind);
+                harness.ents.set_position(id, Vec2::new(i as f32, 1.0));
+                id
+            })
+            .collect::<Vec<_>>();
+
+        let tool = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        tool.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        harness.deliver(
+            Packet::new(OpCode::ListEntities, NoData),
+            tool.local_addr().unwrap(),
+        );
+
+        let mut listed = Vec::new();
+        while listed.len() < ids.len() {
+            // a new address is greeted like a client first, the world comes before the list
+            let (packet, _) = Packet::recv_from(&tool).unwrap();
+            if let Ok(list) = EntityList::try_from(packet) {
+                listed.extend(list.entries);
+            }
+        }
+        assert_eq!(listed.len(), ids.len());
+        for (i, (e, id)) in listed.iter().zip(ids).enumerate() {
+            assert_eq!(e.id, id);
+            assert_eq!(harness.ents.get(id).kind(), e.kind);
+            assert_eq!((e.pos.x, e.pos.y), (i as f32, 1.0));
+        }
+    }
+
+    #[test]
+    fn hunters_walk_around_a_wall_in_their_way() {
+        let mut h = Harness::new();
+        // one wall straight between the hunter and the base
+        let map = Map::parse(".\n#\n.\n.\n.");
+        for &pos in map.walls() {
+            let kind = EntityKind::Wall;
+            let dir = Vec2::default();
+            h.ents
+                .spawn(pos, MAP_CELL / 2.0, 0.0, 0.0, dir, SpriteName::None, kind);
+        }
+        let start = Vec2::new(0.0, 20.0);
+        let enemy = EnemyConfig::default();
+        let hunter = h.ents.spawn(
+            start,
+            1.0,
+            enemy.speed,
+            0.0,
+            Vec2::default() - start,
+            SpriteName::None,
+            EntityKind::Enemy,
+        );
+
+        let dt = Duration::from_millis(16);
+        let mut widest = 0.0f32;
+        let mut outcome = None;
+        for _ in 0..600 {
+            outcome = tick(
+                &h.socket,
+                &mut h.ents,
+                &mut h.clients,
+                &mut h.detached,
+                &enemy,
+                dt,
+            );
+            if outcome.is_some() {
+                break;
+            }
+            widest = widest.max(h.ents.get(hunter).pos().x.abs());
+        }
+
+        // straight on it would have been pushed back against the wall forever
+        assert_eq!(outcome, Some(GameOverReason::BaseDestroyed));
+        assert!(widest >= MAP_CELL / 2.0);
+    }
+
+    #[test]
+    fn fast_spit_hits_the_first_hunter_it_passes_through() {
+        let mut h = Harness::new();
+        let near = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        h.ents.set_position(near, Vec2::new(10.0, 0.0));
+        let far = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        h.ents.set_position(far, Vec2::new(20.0, 0.0));
+        let spit = h.ents.spawn(
+            Vec2::default(),
+            1.0,
+            30.0,
+            0.0,
+            Vec2::new(1.0, 0.0),
+            SpriteName::None,
+            EntityKind::PlayerProjectile,
+        );
+
+        // one long tick carries it from 0 to 30, past both hunters
+        h.ents.tick(1.0);
+        assert_eq!(h.ents.get(spit).pos().x, 30.0);
+        let kills = collide(&mut h.ents, &h.socket, &h.clients, false);
+
+        assert_eq!(kills.len(), 1);
+        assert_eq!(kills[0].x, 10.0);
+        assert!(!h.ents.contains(spit));
+        assert!(!h.ents.contains(near));
+        assert!(h.ents.contains(far));
+    }
+
+    #[test]
+    fn closest_on_segment_clamps_to_the_ends() {
+        let a = Vec2::new(0.0, 0.0);
+        let b = Vec2::new(10.0, 0.0);
+        let at = |p| {
+            let (along, q): (f32, Vec2) = closest_on_segment(a, b, p);
+            (along, q.x, q.y)
+        };
+        assert_eq!(at(Vec2::new(4.0, 3.0)), (0.4, 4.0, 0.0));
+        assert_eq!(at(Vec2::new(-5.0, 1.0)), (0.0, 0.0, 0.0));
+        assert_eq!(at(Vec2::new(15.0, -1.0)), (1.0, 10.0, 0.0));
+        // a spit that didn't move is just a point
+        assert_eq!(closest_on_segment(a, a, b).0, 0.0);
+    }
+
+    #[test]
+    fn spit_is_capped_per_deer_until_some_expires() {
+        let mut h = Harness::new();
+        h.config.weapon.max_live_projectiles = 2;
+        h.config.weapon.projectile_ttl = Duration::from_millis(100);
+        let first = address(1);
+        let second = address(2);
+        let deer = h.join(first);
+        let other = h.join(second);
+
+        let shoot = |h: &mut Harness, from, id| {
+            let request = Fire {
+                correlation: 0,
+                dir: Vec2::new(1.0, 0.0),
+            };
+            // a fresh cooldown every time, only the cap is in the way
+            fire(
+                &h.socket,
+                from,
+                &mut h.ents,
+                id,
+                request,
+                &mut None,
+                &h.config,
+            );
+        };
+        let spit_of = |h: &Harness, id| {
+            h.ents
+                .iter_kind(EntityKind::PlayerProjectile)
+                .filter(|(_, p)| p.owner() == Some(id))
+                .count()
+        };
+
+        for _ in 0..3 {
+            shoot(&mut h, first, deer);
+        }
+        assert_eq!(spit_of(&h, deer), 2);
+        assert_eq!(h.socket.take_traffic().dropped, 1);
+
+        // someone else's spit doesn't count against it
+        shoot(&mut h, second, other);
+        assert_eq!(spit_of(&h, other), 1);
+
+        h.ents.tick(0.2);
+        assert_eq!(spit_of(&h, deer), 0);
+        shoot(&mut h, first, deer);
+        assert_eq!(spit_of(&h, deer), 1);
+    }
+
+    #[test]
+    fn the_default_ping_interval_fits_the_shortest_timeout() {
+        let config = GameConfig::default();
+        let liveness = Liveness::new(config.min_timeout());
+        assert!(config.check_ping_interval(liveness.timeout()));
+        assert!(config.check_ping_interval(TIMEOUT));
+
+        // a slow ping raises the floor rather than timing everyone out between pings
+        let slow = GameConfig {
+            ping_interval: Duration::from_secs(3),
+            ..config
+        };
+        assert!(!slow.check_ping_interval(MIN_TIMEOUT));
+        assert!(slow.check_ping_interval(Liveness::new(slow.min_timeout()).timeout()));
+    }
+
+    #[test]
+    fn rtt_is_measured_from_each_clients_own_ping() {
+        let mut liveness = Liveness::new(MIN_TIMEOUT);
+        // nothing was sent, so there's nothing to measure
+        liveness.pong();
+        assert_eq!(liveness.rtt(), Duration::ZERO);
+
+        liveness.pinged();
+        thread::sleep(Duration::from_millis(20));
+        liveness.pong();
+        let rtt = liveness.rtt();
+        assert!(rtt >= Duration::from_millis(20) / 8);
+
+        // a second pong for the same ping is ignored
+        thread::sleep(Duration::from_millis(20));
+        liveness.pong();
+        assert_eq!(liveness.rtt(), rtt);
+    }
+
+    #[test]
+    fn a_client_heard_within_its_window_is_not_purged() {
+        let mut h = Harness::new();
+        let client = address(40001);
+        h.join(client);
+        let enemy = EnemyConfig::default();
+
+        let window = h.clients[&client].liveness.timeout();
+        assert!(window < TIMEOUT);
+        for _ in 0..5 {
+            let dt = window - Duration::from_millis(100);
+            tick(
+                &h.socket,
+                &mut h.ents,
+                &mut h.clients,
+                &mut h.detached,
+                &enemy,
+                dt,
+            );
+            assert!(h.clients.contains_key(&client));
+            h.deliver(Packet::new(socket::OpCode::Pong, socket::NoData), client);
+        }
+
+        let window = h.clients[&client].liveness.timeout();
+        tick(
+            &h.socket,
+            &mut h.ents,
+            &mut h.clients,
+            &mut h.detached,
+            &enemy,
+            window,
+        );
+        assert!(!h.clients.contains_key(&client));
+    }
+
+    #[test]
+    fn connecting_a_client_counts_it_on_the_server() {
+        let mut h = Harness::new();
+        assert_eq!(h.socket.client_count(), 0);
+
+        let first = address(40001);
+        h.join(first);
+        assert_eq!(h.socket.client_count(), 1);
+        h.join(address(40002));
+        assert_eq!(h.socket.client_count(), 2);
+
+        drop_client(first, &h.socket, &mut h.ents, &mut h.clients);
+        assert_eq!(h.socket.client_count(), 1);
+        assert_eq!(h.socket.client_addresses(), vec![address(40002)]);
+    }
+
+    #[test]
+    fn a_client_that_drops_gets_its_deer_back_when_it_reconnects() {
+        let mut h = Harness::new();
+        let enemy = EnemyConfig::default();
+        let token = Identify { token: 7 };
+        let first = address(40001);
+        h.deliver(token, first);
+        let deer = h.join(first);
+        h.ents.set_position(deer, Vec2::new(5.0, -3.0));
+
+        // the client goes quiet until the server gives up on it
+        tick(
+            &h.socket,
+            &mut h.ents,
+            &mut h.clients,
+            &mut h.detached,
+            &enemy,
+            MAX_TIMEOUT,
+        );
+        assert!(!h.clients.contains_key(&first));
+        assert!(h.ents.contains(deer));
+
+        // and comes back from a new port with the same token
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let again = client.local_addr().unwrap();
+        h.deliver(token, again);
+        h.deliver(spawn_packet(EntityKind::Player, Vec2::default()), again);
+        assert_eq!(h.clients[&again].player_id, Some(deer));
+        assert_eq!(h.ents.count_of_kind(EntityKind::Player), 1);
+
+        // it's moved onto its deer rather than sent it as someone else's
+        let moved = loop {
+            let (packet, _) = Packet::recv_from(&client).unwrap();
+            if let Ok(spawn) = EntitySpawn::try_from(packet.clone()) {
+                assert_ne!(spawn.id, deer);
+            } else if let Ok(update) = EntityUpdate::try_from(packet) {
+                break update;
+            }
+        };
+        assert_eq!(moved.id, 0);
+        assert_eq!((moved.pos.x, moved.pos.y), (5.0, -3.0));
+
+        // a client that never comes back loses its deer once the grace runs out
+        tick(
+            &h.socket,
+            &mut h.ents,
+            &mut h.clients,
+            &mut h.detached,
+            &enemy,
+            MAX_TIMEOUT,
+        );
+        assert!(h.ents.contains(deer));
+        tick(
+            &h.socket,
+            &mut h.ents,
+            &mut h.clients,
+            &mut h.detached,
+            &enemy,
+            RECONNECT_GRACE,
+        );
+        assert!(!h.ents.contains(deer));
+        let destroyed = h
+            .events
+            .try_iter()
+            .filter_map(|p| EntityDestroy::try_from(p).ok());
+        assert!(destroyed.map(|e| e.id).any(|id| id == deer));
+    }
+
+    #[test]
+    fn a_client_gets_no_updates_for_a_distant_entity() {
+        let mut h = Harness::new();
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let address = client.local_addr().unwrap();
+        h.join(address);
+        let hunter = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        h.ents.set_position(hunter, Vec2::new(100.0, 0.0));
+
+        let mut last_sent = HashMap::new();
+        let mut broadcast = |h: &mut Harness| {
+            broadcast_moved(
+                &h.ents,
+                &HashSet::new(),
+                &mut last_sent,
+                0.0,
+                Some(10.0),
+                &h.socket,
+                &mut h.clients,
+            )
+        };
+        // everything that reached the client since the last look
+        let received = || {
+            let mut packets = Vec::new();
+            while let Ok((packet, _)) = Packet::recv_from(&client) {
+                packets.push(packet);
+            }
+            packets
+        };
+        let updates_for = |packets: &[Packet], id| {
+            packets
+                .iter()
+                .filter_map(|p| EntityUpdate::try_from(p.clone()).ok())
+                .filter(|u| u.id == id)
+                .count()
+        };
+
+        assert!(broadcast(&mut h).is_empty());
+        h.ents.set_position(hunter, Vec2::new(90.0, 0.0));
+        assert!(broadcast(&mut h).is_empty());
+        assert_eq!(updates_for(&received(), hunter), 0);
+
+        // in range it's updated, and once it's been culled it comes back with a spawn
+        h.ents.set_position(hunter, Vec2::new(5.0, 0.0));
+        assert!(broadcast(&mut h).is_empty());
+        assert_eq!(updates_for(&received(), hunter), 1);
+        h.ents.set_position(hunter, Vec2::new(50.0, 0.0));
+        broadcast(&mut h);
+        h.ents.set_position(hunter, Vec2::new(4.0, 0.0));
+        assert_eq!(broadcast(&mut h), vec![(address, hunter)]);
+        assert_eq!(updates_for(&received(), hunter), 1);
+    }
+
+    #[test]
+    fn a_joining_client_is_sent_every_live_hunter_and_deer() {
+        let mut h = Harness::new();
+        let hunters = (0..3)
+            .map(|_| spawn_kind(&mut h.ents, EntityKind::Enemy))
+            .collect::<Vec<_>>();
+        let first = h.join(address(40001));
+        spawn_kind(&mut h.ents, EntityKind::Tile);
+
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let second = client.local_addr().unwrap();
+        h.deliver(Packet::new(OpCode::Ready, NoData), second);
+
+        let mut spawned = HashMap::new();
+        while let Ok((packet, _)) = Packet::recv_from(&client) {
+            if let Ok(spawn) = EntitySpawn::try_from(packet) {
+                spawned.insert(spawn.id, spawn.kind);
+            }
+        }
+        let mut expected = hunters
+            .iter()
+            .map(|&id| (id, EntityKind::Enemy))
+            .collect::<HashMap<_, _>>();
+        expected.insert(first, EntityKind::Player);
+        // tiles are generated from the world seed
+        assert_eq!(spawned, expected);
+    }
+
+    #[test]
+    fn short_packets_from_a_client_are_dropped() {
+        let mut h = Harness::new();
+        let client = address(40001);
+        h.join(client);
+        for op in [
+            OpCode::EntityUpdate,
+            OpCode::EntityDestroy,
+            OpCode::SpawnAck,
+            OpCode::Fire,
+            OpCode::Input,
+        ] {
+            h.deliver(Packet::new(op, [1]), client);
+        }
+        assert_eq!(h.socket.take_traffic().dropped, 5);
+    }
+
+    #[test]
+    fn only_the_shooter_is_told_which_spit_its_shot_became() {
+        let mut h = Harness::new();
+        let listen = || {
+            let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            socket
+        };
+        let drain = |socket: &std::net::UdpSocket| {
+            let mut packets = Vec::new();
+            while let Ok((packet, _)) = Packet::recv_from(socket) {
+                packets.push(packet);
+            }
+            packets
+        };
+        let (owner, other) = (listen(), listen());
+        h.join(owner.local_addr().unwrap());
+        h.join(other.local_addr().unwrap());
+        drain(&owner);
+        drain(&other);
+
+        let shot = Fire {
+            correlation: 9,
+            dir: Vec2::new(1.0, 0.0),
+        };
+        h.deliver(shot, owner.local_addr().unwrap());
+        let (spit, _) = h
+            .ents
+            .iter_kind(EntityKind::PlayerProjectile)
+            .next()
+            .unwrap();
+
+        let to_owner = drain(&owner);
+        let acks = to_owner
+            .iter()
+            .filter_map(|p| FireAck::try_from(p.clone()).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(acks.len(), 1);
+        assert_eq!((acks[0].correlation, acks[0].id), (9, spit));
+
+        let to_other = drain(&other);
+        assert!(to_other.iter().all(|p| OpCode::FireAck != p.opcode()));
+        let spawns = to_other
+            .iter()
+            .filter_map(|p| EntitySpawn::try_from(p.clone()).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(spawns.len(), 1);
+        assert_eq!(spawns[0].id, spit);
+        assert_eq!(spawns[0].kind, EntityKind::PlayerProjectile);
+    }
+
+    #[test]
+    fn an_oversize_spawn_is_broadcast_clamped() {
+        let mut h = Harness::new();
+        let watcher = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        watcher
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        h.join(watcher.local_addr().unwrap());
+        while Packet::recv_from(&watcher).is_ok() {}
+
+        let cheat = EntitySpawn {
+            scale: MAX_CLIENT_SCALE * 10.0,
+            speed: MAX_CLIENT_SPEED * 10.0,
+            ..spawn_packet(EntityKind::Player, Vec2::new(50.0, 50.0))
+        };
+        let cheater = address(40002);
+        h.deliver(cheat, cheater);
+        let deer = h.clients[&cheater].player_id.unwrap();
+
+        let spawn = loop {
+            let (packet, _) = Packet::recv_from(&watcher).unwrap();
+            if let Ok(spawn) = EntitySpawn::try_from(packet) {
+                break spawn;
+            }
+        };
+        assert_eq!(spawn.id, deer);
+        assert_eq!(spawn.scale, MAX_CLIENT_SCALE);
+        assert_eq!(spawn.speed, MAX_CLIENT_SPEED);
+        assert_eq!(h.ents.get(deer).scale(), MAX_CLIENT_SCALE);
+    }
+
+    #[test]
+    fn a_kicked_client_is_told_and_its_deer_destroyed() {
+        let mut h = Harness::new();
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let address = client.local_addr().unwrap();
+        // a kick isn't a drop, it doesn't wait for the client to come back
+        h.deliver(Identify { token: 3 }, address);
+        let deer = h.join(address);
+        while Packet::recv_from(&client).is_ok() {}
+
+        kick_client(address, &h.socket, &mut h.ents, &mut h.clients);
+        assert!(!h.clients.contains_key(&address));
+        assert!(!h.ents.contains(deer));
+        let destroyed = h
+            .events
+            .try_iter()
+            .filter_map(|p| EntityDestroy::try_from(p).ok());
+        assert!(destroyed.map(|e| e.id).any(|id| id == deer));
+
+        let (bye, _) = Packet::recv_from(&client).unwrap();
+        assert!(socket::OpCode::Bye == bye.opcode());
+    }
+
+    #[test]
+    fn hunters_spawn_inside_the_world() {
+        let mut ents = entities::EntityManager::default();
+        let mut rng = StdRng::seed_from_u64(1198);
+        let enemy = EnemyConfig::default();
+        let bound = WORLD_SIZE as f32;
+        for _ in 0..1000 {
+            let id = spawn_hunter(&mut ents, &enemy, &mut rng);
+            let pos = ents.get(id).pos();
+            assert!(
+                pos.x.abs() <= bound && pos.y.abs() <= bound,
+                "{:?}",
+                (pos.x, pos.y)
+            );
+            ents.destroy(id);
+        }
+    }
+
+    #[test]
+    fn a_hunter_is_spawned_walks_in_and_is_destroyed_at_the_middle() {
+        let mut h = Harness::new();
+        let enemy = EnemyConfig::default();
+        let start = Vec2::new(0.0, 10.0);
+        let hunter = h.ents.spawn(
+            start,
+            enemy.scale,
+            enemy.speed,
+            0.0,
+            Vec2::default() - start,
+            SpriteName::None,
+            EntityKind::Enemy,
+        );
+
+        let dt = Duration::from_millis(16);
+        let mut outcome = None;
+        for _ in 0..1000 {
+            h.ents.tick(dt.as_secs_f32());
+            outcome = tick(
+                &h.socket,
+                &mut h.ents,
+                &mut h.clients,
+                &mut h.detached,
+                &enemy,
+                dt,
+            );
+            if outcome.is_some() {
+                break;
+            }
+        }
+        assert_eq!(outcome, Some(GameOverReason::BaseDestroyed));
+        assert!(!h.ents.contains(hunter));
+
+        let events = h.events.try_iter().collect::<Vec<_>>();
+        let spawned = events
+            .iter()
+            .position(|p| EntitySpawn::try_from(p.clone()).is_ok_and(|e| e.id == hunter));
+        let destroyed = events
+            .iter()
+            .position(|p| EntityDestroy::try_from(p.clone()).is_ok_and(|e| e.id == hunter));
+        assert!(spawned.unwrap() < destroyed.unwrap());
+    }
+
+    #[test]
+    fn a_dropped_spawn_is_resent_until_it_is_acked() {
+        let mut h = Harness::new();
+        let watcher = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        watcher
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let address = watcher.local_addr().unwrap();
+        h.join(address);
+        while Packet::recv_from(&watcher).is_ok() {}
+        let spawns = || {
+            std::iter::from_fn(|| Packet::recv_from(&watcher).ok())
+                .filter_map(|(p, _)| EntitySpawn::try_from(p).ok())
+                .map(|e| e.id)
+                .collect::<Vec<_>>()
+        };
+
+        let hunter = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        flush_events(&h.events, &h.socket, None, &h.clients, &mut h.acks);
+        // the first one is lost on the way
+        assert_eq!(spawns(), vec![hunter]);
+
+        h.acks.tick(ACK_WINDOW, &h.socket, &h.clients, &h.ents);
+        assert_eq!(spawns(), vec![hunter]);
+
+        h.deliver(SpawnAck { id: hunter }, address);
+        h.acks.tick(ACK_WINDOW, &h.socket, &h.clients, &h.ents);
+        assert!(spawns().is_empty());
+    }
+
+    #[test]
+    fn entities_keep_their_server_ids_across_a_resync() {
+        let mut h = Harness::new();
+        let hunters = (0..3)
+            .map(|_| spawn_kind(&mut h.ents, EntityKind::Enemy))
+            .collect::<Vec<_>>();
+        let other = h.join(address(40001));
+        let resync = |h: &mut Harness| {
+            let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            let address = client.local_addr().unwrap();
+            h.deliver(Packet::new(OpCode::Ready, NoData), address);
+            let mut spawned = HashMap::new();
+            while let Ok((packet, _)) = Packet::recv_from(&client) {
+                if let Ok(spawn) = EntitySpawn::try_from(packet) {
+                    spawned.insert(spawn.id, spawn.kind);
+                }
+            }
+            (address, spawned)
+        };
+
+        let (first, before) = resync(&mut h);
+        drop_client(first, &h.socket, &mut h.ents, &mut h.clients);
+        // entities come and go while it's away, the new one doesn't take the old id
+        h.ents.destroy(hunters[0]);
+        let late = spawn_kind(&mut h.ents, EntityKind::Enemy);
+        assert!(!before.contains_key(&late));
+
+        let (_, after) = resync(&mut h);
+        for id in [hunters[1], hunters[2], other] {
+            assert_eq!(after.get(&id), before.get(&id));
+        }
+        assert!(!after.contains_key(&hunters[0]));
+        assert_eq!(after.get(&late), Some(&EntityKind::Enemy));
+    }
+
+    #[test]
+    fn rapid_fire_shortens_the_cooldown_until_it_wears_off() {
+        let mut h = Harness::new();
+        let client = address(40001);
+        let deer = h.join(client);
+        let cooldown = h.config.weapon.cooldown * FIRE_SLACK_PERCENT / 100;
+        // too soon for a normal shot, late enough for a rapid one
+        let shoot = |h: &mut Harness| {
+            let request = Fire {
+                correlation: 0,
+                dir: Vec2::new(1.0, 0.0),
+            };
+            let mut last_fire = Some(Instant::now() - cooldown * 3 / 4);
+            fire(
+                &h.socket,
+                client,
+                &mut h.ents,
+                deer,
+                request,
+                &mut last_fire,
+                &h.config,
+            );
+            h.socket.take_traffic().dropped == 0
+        };
+
+        assert!(!shoot(&mut h));
+        let duration = Duration::from_secs(1);
+        h.ents
+            .get_mut(deer)
+            .add_effect(Effect::Powerup(Powerup::RapidFire), duration);
+        assert!(shoot(&mut h));
+        assert!(shoot(&mut h));
+
+        h.ents.tick(duration.as_secs_f32() + 0.1);
+        assert!(!shoot(&mut h));
+    }
+
+    #[test]
+    fn holding_right_moves_the_deer_by_its_speed_on_the_server() {
+        let mut h = Harness::new();
+        let client = address(40001);
+        let deer = h.join(client);
+        let right = PlayerInput {
+            right: true,
+            ..Default::default()
+        };
+        h.deliver(
+            Input {
+                seq: 1,
+                input: right,
+            },
+            client,
+        );
+        for _ in 0..30 {
+            h.ents.tick(1.0 / 60.0);
+        }
+        // half a second at the spawn's speed of 12
+        let pos = h.ents.get(deer).pos();
+        assert!(
+            (pos.x - 6.0).abs() < 1e-3 && pos.y.abs() < 1e-3,
+            "{:?}",
+            pos
+        );
+
+        // reported positions don't count once input does
+        let cheat = EntityUpdate {
+            id: 0,
+            pos: Vec2::new(100.0, 0.0),
+            rotation: 0.0,
+        };
+        h.deliver(cheat, client);
+        assert!((h.ents.get(deer).pos().x - 6.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn a_timed_out_clients_deer_is_destroyed_once_for_everyone_left() {
+        let mut h = Harness::new();
+        let enemy = EnemyConfig::default();
+        let watcher = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        watcher
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let stays = watcher.local_addr().unwrap();
+        h.join(stays);
+        let leaves = address(40001);
+        let deer = h.join(leaves);
+        // it goes quiet halfway through its resync
+        h.deliver(Packet::new(OpCode::Ready, NoData), leaves);
+        flush_events(&h.events, &h.socket, None, &h.clients, &mut h.acks);
+        while Packet::recv_from(&watcher).is_ok() {}
+
+        let window = h.clients[&leaves].liveness.timeout();
+        let step = |h: &mut Harness, dt| {
+            tick(
+                &h.socket,
+                &mut h.ents,
+                &mut h.clients,
+                &mut h.detached,
+                &enemy,
+                dt,
+            );
+            flush_events(&h.events, &h.socket, None, &h.clients, &mut h.acks);
+        };
+        step(&mut h, window - Duration::from_millis(100));
+        h.deliver(Packet::new(socket::OpCode::Pong, NoData), stays);
+        step(&mut h, Duration::from_millis(200));
+        assert!(!h.clients.contains_key(&leaves));
+        assert!(h.clients.contains_key(&stays));
+        step(&mut h, Duration::from_millis(200));
+
+        let destroys = std::iter::from_fn(|| Packet::recv_from(&watcher).ok())
+            .filter_map(|(p, _)| EntityDestroy::try_from(p).ok())
+            .filter(|e| e.id == deer)
+            .count();
+        assert_eq!(destroys, 1);
+    }
 }
@@ -1,20 +1,42 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::io;
+use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::net::UdpSocket;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
 use std::time::Duration;
+use std::time::Instant;
 
 const DEFAULT_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::UNSPECIFIED, 0);
 
+/// sent with Hello, bump whenever a packet layout changes
+/// unversioned peers read as 0
+pub const PROTOCOL_VERSION: u8 = 4;
+
+/// largest datagram either side will receive, opcode included
+/// anything bigger would be truncated, so sending it is an error until packets can be split
+/// and receiving it is reported rather than parsed
+pub const MAX_PACKET_SIZE: usize = 256;
+
 #[derive(Debug)]
 pub enum Error {
     NotEnoughData,
     BadAddress,
     BadOpcode,
+    VersionMismatch,
+    Disconnected,
+    PacketTooLarge,
+    /// a field holds a value no sender would write, like an unknown enum variant
+    BadData,
     IoError(io::Error),
 }
 
@@ -33,6 +55,10 @@ impl Display for Error {
             Error::NotEnoughData => "not enough data in received message",
             Error::BadAddress => "bad address/port",
             Error::BadOpcode => "bad opcode",
+            Error::VersionMismatch => "peer speaks a different protocol version",
+            Error::Disconnected => "disconnected by remote",
+            Error::PacketTooLarge => "packet exceeds MAX_PACKET_SIZE",
+            Error::BadData => "malformed packet data",
             Error::IoError(_) => "std::io::error: ",
         };
 
@@ -55,7 +81,7 @@ impl From<Infallible> for Error {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     /// Sent by the client to initiat a connection
     /// Sent by the server to aknowledge the connection
@@ -68,8 +94,12 @@ pub enum OpCode {
     Ping,
 
     /// KeepAlive response
+    /// from the server it carries the server's uptime in milliseconds as a u32
     Pong,
 
+    /// Sent by the server to close the connection (kick)
+    Bye,
+
     /// allows users to have their own opcode enums without using reserved opcode values
     /// user defined enums should define:
     /// From<u8>, Into<u8>, Clone, Copy, PartialEq
@@ -87,17 +117,56 @@ impl From<OpCode> for u8 {
 
 impl From<u8> for OpCode {
     fn from(value: u8) -> Self {
+        // every user opcode is past the last reserved one
+        if value >= OpCode::UserDefined as u8 {
+            return OpCode::UserDefined;
+        }
         unsafe { std::mem::transmute(value) }
     }
 }
 
+static OPCODE_NAMES: RwLock<BTreeMap<u8, String>> = RwLock::new(BTreeMap::new());
+
+/// gives a user defined opcode a readable name for logging
+pub fn register_opcode_name(opcode: u8, name: &str) {
+    OPCODE_NAMES
+        .write()
+        .unwrap()
+        .insert(opcode, name.to_owned());
+}
+
+/// formats an opcode by name if it is reserved or registered, by value otherwise
+pub struct OpCodeName(pub u8);
+
+impl Debug for OpCodeName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0 < OpCode::UserDefined as u8 {
+            return write!(f, "{:?}", OpCode::from(self.0));
+        }
+
+        match OPCODE_NAMES.read().unwrap().get(&self.0) {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Packet {
     pub opcode: u8,
     pub data: Vec<u8>,
 }
 
+impl Debug for Packet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Packet")
+            .field("opcode", &OpCodeName(self.opcode))
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
 pub struct NoData;
 
 impl AsRef<[u8]> for NoData {
@@ -124,6 +193,14 @@ impl Packet {
         }
     }
 
+    /// preallocates room for `capacity` bytes of payload
+    pub fn with_capacity<O: Into<u8>>(op: O, capacity: usize) -> Self {
+        Self {
+            opcode: op.into(),
+            data: Vec::with_capacity(capacity),
+        }
+    }
+
     pub fn opcode<T: From<u8>>(&self) -> T {
         self.opcode.into()
     }
@@ -132,23 +209,34 @@ impl Packet {
         self.data
     }
 
-    pub fn send_to(self, socket: &UdpSocket, address: Option<SocketAddr>) -> Result<()> {
-        let mut buf = vec![self.opcode];
-        buf.extend(self.data.into_iter());
-        if let Some(address) = address {
-            socket
-                .send_to(&buf, address)
-                .and(Ok(()))
-                .map_err(Into::into)
-        } else {
-            socket.send(&buf).and(Ok(())).map_err(Into::into)
+    /// the opcode followed by the payload, exactly what goes on the wire
+    pub fn into_bytes(self) -> Result<Vec<u8>> {
+        if 1 + self.data.len() > MAX_PACKET_SIZE {
+            return Err(Error::PacketTooLarge);
         }
+
+        let mut buf = Vec::with_capacity(1 + self.data.len());
+        buf.push(self.opcode);
+        buf.extend_from_slice(&self.data);
+        Ok(buf)
     }
 
+    pub fn send_to(self, socket: &UdpSocket, address: Option<SocketAddr>) -> Result<()> {
+        send_bytes(socket, &self.into_bytes()?, address)
+    }
+
+    /// a datagram longer than MAX_PACKET_SIZE is an error rather than a truncated packet
     pub fn recv_from(socket: &UdpSocket) -> Result<(Self, SocketAddr)> {
-        const LEN: usize = 256;
-        let mut buf = vec![0; LEN];
-        let (_, addr) = socket.recv_from(&mut buf)?;
+        // one spare byte, filling it means the OS cut the datagram short
+        let mut buf = vec![0; MAX_PACKET_SIZE + 1];
+        let (len, addr) = socket.recv_from(&mut buf)?;
+        if len > MAX_PACKET_SIZE {
+            return Err(Error::PacketTooLarge);
+        }
+        if len == 0 {
+            return Err(Error::NotEnoughData);
+        }
+        buf.truncate(len);
         Ok((
             Self {
                 opcode: buf.remove(0),
@@ -159,6 +247,18 @@ impl Packet {
     }
 }
 
+/// sends an already serialized packet
+fn send_bytes(socket: &UdpSocket, bytes: &[u8], address: Option<SocketAddr>) -> Result<()> {
+    if let Some(address) = address {
+        socket
+            .send_to(bytes, address)
+            .and(Ok(()))
+            .map_err(Into::into)
+    } else {
+        socket.send(bytes).and(Ok(())).map_err(Into::into)
+    }
+}
+
 pub struct Client {
     socket: UdpSocket,
 }
@@ -172,15 +272,18 @@ impl Client {
         Ok(Self { socket })
     }
 
-    pub fn connect<A: ToSocketAddrs>(&self, address: A) -> Result<()> {
+    /// returns whatever the server put in its Hello reply after the protocol version
+    pub fn connect<A: ToSocketAddrs>(&self, address: A) -> Result<Vec<u8>> {
         let address = address.to_socket_addrs()?.next().ok_or(Error::BadAddress)?;
         self.socket.connect(address)?;
-        self.send(Packet::new(OpCode::Hello, NoData))?;
+        self.send(Packet::new(OpCode::Hello, [PROTOCOL_VERSION]))?;
         let hello_reply: Packet = self.recv()?;
         if OpCode::Hello != hello_reply.opcode() {
             Err(Error::BadOpcode)
+        } else if hello_reply.data.first() != Some(&PROTOCOL_VERSION) {
+            Err(Error::VersionMismatch)
         } else {
-            Ok(())
+            Ok(hello_reply.data[1..].to_vec())
         }
     }
 
@@ -193,9 +296,11 @@ impl Client {
             let (packet, _) = Packet::recv_from(&self.socket)?;
             match packet.opcode() {
                 OpCode::Ping => self.send(Packet::new(OpCode::Pong, NoData))?,
+                OpCode::Bye => return Err(Error::Disconnected),
                 OpCode::Port => {
-                    let port = Vec::from(packet)[..2].try_into().unwrap();
-                    self.set_remote_port(u16::from_ne_bytes(port))?;
+                    let data = Vec::from(packet);
+                    let port = data.get(..2).ok_or(Error::NotEnoughData)?;
+                    self.set_remote_port(u16::from_be_bytes(port.try_into().unwrap()))?;
                 }
                 _ => break packet,
             }
@@ -211,23 +316,123 @@ impl Client {
     }
 }
 
+/// packet counts since they were last taken
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+    pub dropped: u64,
+}
+
 pub struct Server {
     socket: UdpSocket,
+    sent: AtomicU64,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    banned: RwLock<HashSet<IpAddr>>,
+    /// addresses the game has heard from and not dropped yet
+    clients: RwLock<HashSet<SocketAddr>>,
+    /// sent after the protocol version in every Hello reply
+    hello_data: RwLock<Vec<u8>>,
+    /// pongs carry the time since this so clients can estimate the server's clock
+    started: Instant,
 }
 
 impl Server {
     pub fn listen(port: u16) -> Result<Self> {
         let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
-        Ok(Self { socket })
+        Ok(Self {
+            socket,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            banned: RwLock::new(HashSet::new()),
+            clients: RwLock::new(HashSet::new()),
+            hello_data: RwLock::new(Vec::new()),
+            started: Instant::now(),
+        })
+    }
+
+    /// tells a client it's been dropped
+    pub fn kick(&self, address: SocketAddr) -> Result<()> {
+        self.forget(address);
+        self.send(Packet::new(OpCode::Bye, NoData), address)
+    }
+
+    /// everything from a banned address is ignored, including Hello
+    pub fn ban(&self, ip: IpAddr) {
+        self.banned.write().unwrap().insert(ip);
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.banned.read().unwrap().contains(&ip)
+    }
+
+    /// what clients need to know before the first game packet, like the world they're joining
+    pub fn set_hello_data(&self, data: Vec<u8>) {
+        *self.hello_data.write().unwrap() = data;
+    }
+
+    /// counts `address` as connected until it's forgotten or kicked
+    pub fn connect(&self, address: SocketAddr) {
+        self.clients.write().unwrap().insert(address);
+    }
+
+    /// for clients that left without being kicked, like ones that timed out
+    pub fn forget(&self, address: SocketAddr) {
+        self.clients.write().unwrap().remove(&address);
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.read().unwrap().len()
+    }
+
+    /// in no particular order
+    pub fn client_addresses(&self) -> Vec<SocketAddr> {
+        self.clients.read().unwrap().iter().copied().collect()
+    }
+
+    /// the server's clock, as clients see it through pongs
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// returns the packet counts and starts counting from zero
+    pub fn take_traffic(&self) -> Traffic {
+        Traffic {
+            sent: self.sent.swap(0, Ordering::Relaxed),
+            received: self.received.swap(0, Ordering::Relaxed),
+            dropped: self.dropped.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
     }
 
     /// connectionless mode
     pub fn recv<E: Into<Error>, P: TryFrom<Packet, Error = E>>(&self) -> Result<(P, SocketAddr)> {
         let (packet, address) = loop {
             let (packet, address) = Packet::recv_from(&self.socket)?;
+            self.received.fetch_add(1, Ordering::Relaxed);
+            if self.is_banned(address.ip()) {
+                self.record_dropped();
+                continue;
+            }
             match packet.opcode() {
-                OpCode::Hello => self.send(Packet::new(OpCode::Hello, NoData), address)?,
-                OpCode::Ping => self.send(Packet::new(OpCode::Pong, NoData), address)?,
+                // a client on another version would misread everything, let it time out
+                OpCode::Hello if packet.data.first() != Some(&PROTOCOL_VERSION) => {
+                    self.record_dropped()
+                }
+                OpCode::Hello => {
+                    let mut reply = vec![PROTOCOL_VERSION];
+                    reply.extend_from_slice(&self.hello_data.read().unwrap());
+                    self.send(Packet::new(OpCode::Hello, reply), address)?
+                }
+                OpCode::Ping => {
+                    let millis = self.uptime().as_millis() as u32;
+                    self.send(Packet::new(OpCode::Pong, millis.to_be_bytes()), address)?
+                }
                 _ => break (packet, address),
             }
         };
@@ -238,7 +443,16 @@ impl Server {
 
     /// connectionless mode
     pub fn send<P: Into<Packet>>(&self, packet: P, address: SocketAddr) -> Result<()> {
-        packet.into().send_to(&self.socket, Some(address))
+        packet.into().send_to(&self.socket, Some(address))?;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// sends bytes from `Packet::into_bytes`, so a broadcast only serializes once
+    pub fn send_bytes(&self, bytes: &[u8], address: SocketAddr) -> Result<()> {
+        send_bytes(&self.socket, bytes, Some(address))?;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
     }
 
     // connectionful mode
@@ -251,7 +465,7 @@ impl Server {
     //             client.socket.connect(address)?;
 
     //             let new_port = client.socket.local_addr().unwrap().port();
-    //             Packet::new(OpCode::Port, &new_port.to_ne_bytes())
+    //             Packet::new(OpCode::Port, &new_port.to_be_bytes())
     //                 .send_to(&self.socket, Some(address))?;
     //             sleep(Duration::from_millis(1));
     //             client.send(Packet::new(OpCode::Hello, NoData))?;
@@ -277,3 +491,128 @@ impl Server {
     //     unimplemented!()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn oversize_packets_are_refused_instead_of_truncated() {
+        let fits = Packet::new(OpCode::UserDefined, vec![0; MAX_PACKET_SIZE - 1]);
+        assert_eq!(fits.into_bytes().unwrap().len(), MAX_PACKET_SIZE);
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let to = socket.local_addr().unwrap();
+        let big = Packet::new(OpCode::UserDefined, vec![0; MAX_PACKET_SIZE]);
+        assert!(matches!(
+            big.send_to(&socket, Some(to)),
+            Err(Error::PacketTooLarge)
+        ));
+
+        // and a datagram that big from someone else is refused on the way in
+        socket.send_to(&[0; MAX_PACKET_SIZE + 1], to).unwrap();
+        assert!(matches!(
+            Packet::recv_from(&socket),
+            Err(Error::PacketTooLarge)
+        ));
+    }
+
+    #[test]
+    fn received_packets_keep_only_the_bytes_that_were_sent() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let to = socket.local_addr().unwrap();
+        Packet::new(OpCode::UserDefined, [1, 2, 3])
+            .send_to(&socket, Some(to))
+            .unwrap();
+        let (packet, _) = Packet::recv_from(&socket).unwrap();
+        assert_eq!(packet.data(), vec![1, 2, 3]);
+
+        socket.send_to(&[], to).unwrap();
+        assert!(matches!(
+            Packet::recv_from(&socket),
+            Err(Error::NotEnoughData)
+        ));
+    }
+
+    #[test]
+    fn a_banned_address_gets_no_hello_reply() {
+        let server = Server::listen(0).unwrap();
+        let to = (
+            Ipv4Addr::LOCALHOST,
+            server.socket.local_addr().unwrap().port(),
+        );
+        let peer = |ip: Ipv4Addr| {
+            let socket = UdpSocket::bind((ip, 0)).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            socket
+        };
+        let hello = || {
+            Packet::new(OpCode::Hello, [PROTOCOL_VERSION])
+                .into_bytes()
+                .unwrap()
+        };
+        let (welcome, banned) = (peer(Ipv4Addr::LOCALHOST), peer(Ipv4Addr::new(127, 0, 0, 2)));
+        server.ban(banned.local_addr().unwrap().ip());
+
+        thread::scope(|s| {
+            // answers hellos until the first game packet
+            let recv = s.spawn(|| server.recv::<_, Packet>());
+
+            welcome.send_to(&hello(), to).unwrap();
+            let (reply, _) = Packet::recv_from(&welcome).unwrap();
+            assert!(OpCode::Hello == reply.opcode());
+
+            banned.send_to(&hello(), to).unwrap();
+            assert!(Packet::recv_from(&banned).is_err());
+
+            let game = Packet::new(OpCode::UserDefined, NoData);
+            welcome.send_to(&game.into_bytes().unwrap(), to).unwrap();
+            let (_, from) = recv.join().unwrap().unwrap();
+            assert_eq!(from, welcome.local_addr().unwrap());
+        });
+        assert!(server.take_traffic().dropped >= 1);
+    }
+
+    #[test]
+    fn a_port_packet_moves_the_client_to_the_new_port() {
+        let bind = || {
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            socket
+        };
+        let (greeter, session) = (bind(), bind());
+        let client = Client::new().unwrap();
+        client
+            .socket
+            .connect(greeter.local_addr().unwrap())
+            .unwrap();
+        client
+            .send(Packet::new(OpCode::UserDefined, NoData))
+            .unwrap();
+        let (_, client_address) = greeter.recv_from(&mut [0; 8]).unwrap();
+        let to = (Ipv4Addr::LOCALHOST, client_address.port());
+
+        let port = session.local_addr().unwrap().port();
+        let moved = Packet::new(OpCode::Port, port.to_be_bytes());
+        greeter.send_to(&moved.into_bytes().unwrap(), to).unwrap();
+        // queued behind the move, so its pong comes back from the new port
+        let ping = Packet::new(OpCode::Ping, NoData);
+        greeter.send_to(&ping.into_bytes().unwrap(), to).unwrap();
+
+        thread::scope(|s| {
+            let recv = s.spawn(|| client.recv::<_, Packet>());
+            let (pong, _) = Packet::recv_from(&session).unwrap();
+            assert!(OpCode::Pong == pong.opcode());
+            let game = Packet::new(OpCode::UserDefined, [7]);
+            session.send_to(&game.into_bytes().unwrap(), to).unwrap();
+            assert_eq!(recv.join().unwrap().unwrap().data(), vec![7]);
+        });
+        assert_eq!(client.socket.peer_addr().unwrap().port(), port);
+    }
+}
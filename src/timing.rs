@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// clamps frame times so a single long frame can't teleport entities,
+/// and averages them over the last few frames
+pub struct FrameTimer {
+    max_dt: Duration,
+    window: usize,
+    samples: VecDeque<Duration>,
+}
+
+impl FrameTimer {
+    pub fn new(max_dt: Duration, window: usize) -> Self {
+        Self {
+            max_dt,
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn smooth(&mut self, dt: Duration) -> Duration {
+        self.samples.push_back(dt.min(self.max_dt));
+        if self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+
+        self.average()
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    /// frames per second over the averaging window
+    pub fn fps(&self) -> f32 {
+        let average = self.average();
+        if average.is_zero() {
+            return 0.0;
+        }
+
+        1.0 / average.as_secs_f32()
+    }
+}
+
+/// like engine_2d's Timer, but a dt spanning several periods counts every one of them
+/// instead of firing once and losing the rest
+pub struct Interval {
+    period: Duration,
+    elapsed: Duration,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// how many periods ended during `dt`, usually 0 or 1
+    /// a zero period fires once every tick
+    pub fn tick(&mut self, dt: Duration) -> u32 {
+        if self.period.is_zero() {
+            return 1;
+        }
+        self.elapsed += dt;
+        let fires = (self.elapsed.as_nanos() / self.period.as_nanos()) as u32;
+        self.elapsed -= self.period * fires;
+        fires
+    }
+}
+
+/// how far each new sample moves the clock offset, so one slow pong can't yank it around
+const OFFSET_SMOOTHING: f64 = 0.1;
+
+/// estimates the server's clock from its pongs, NTP style: the server stamped the pong
+/// about half a round trip before it arrived
+pub struct ClockSync {
+    epoch: Instant,
+    /// server time minus time since `epoch`, in seconds
+    offset: Option<f64>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            offset: None,
+        }
+    }
+
+    /// `sent` is when the ping went out, `received` when the pong stamped `server_time` got in
+    pub fn pong(&mut self, sent: Instant, received: Instant, server_time: Duration) {
+        let rtt = received.saturating_duration_since(sent);
+        let local = received.saturating_duration_since(self.epoch);
+        let estimate = server_time.as_secs_f64() + rtt.as_secs_f64() / 2.0 - local.as_secs_f64();
+        self.offset = Some(match self.offset {
+            Some(offset) => offset + OFFSET_SMOOTHING * (estimate - offset),
+            None => estimate,
+        });
+    }
+
+    /// None until the first pong
+    pub fn server_time(&self, now: Instant) -> Option<Duration> {
+        let local = now.saturating_duration_since(self.epoch).as_secs_f64();
+        self.offset
+            .map(|offset| Duration::from_secs_f64((local + offset).max(0.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn interval_catches_up_on_every_missed_period() {
+        let mut interval = Interval::new(ms(100));
+        assert_eq!(interval.tick(ms(350)), 3);
+        assert_eq!(interval.tick(ms(40)), 0);
+        // the 50ms left over from the long frame still counts
+        assert_eq!(interval.tick(ms(10)), 1);
+        assert_eq!(interval.tick(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn zero_interval_fires_every_tick() {
+        let mut interval = Interval::new(Duration::ZERO);
+        assert_eq!(interval.tick(ms(16)), 1);
+        assert_eq!(interval.tick(Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn frame_timer_clamps_then_averages_the_window() {
+        let mut timer = FrameTimer::new(ms(100), 3);
+        assert_eq!(timer.fps(), 0.0);
+        assert_eq!(timer.smooth(Duration::from_secs(2)), ms(100));
+        timer.smooth(ms(10));
+        assert_eq!(timer.smooth(ms(10)), ms(40));
+        // the clamped frame falls out of the window
+        assert_eq!(timer.smooth(ms(10)), ms(10));
+        assert!((timer.fps() - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn clock_sync_adds_half_the_round_trip() {
+        let mut clock = ClockSync::new();
+        assert!(clock.server_time(clock.epoch).is_none());
+
+        let sent = clock.epoch + Duration::from_secs(1);
+        let received = sent + ms(200);
+        clock.pong(sent, received, Duration::from_secs(5));
+        // stamped 100ms before it arrived
+        assert_eq!(clock.server_time(received).unwrap().as_millis(), 5100);
+        let later = received + Duration::from_secs(2);
+        assert_eq!(clock.server_time(later).unwrap().as_millis(), 7100);
+
+        // a pong saying the server is 1s further along only moves the estimate a little
+        clock.pong(later, later + ms(200), ms(8200));
+        let time = clock.server_time(later + ms(200)).unwrap().as_millis();
+        assert_eq!(time, 7300 + 100);
+    }
+}